@@ -1,3 +0,0 @@
-name = "Shattered Universalis"
-path = "mod/shattered"
-supported_version = "1.16"
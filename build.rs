@@ -0,0 +1,51 @@
+//! Hashes the generator's own code, so the build cache can tell when the output of a previous
+//! run came from a different version of the generator.
+
+use std::env;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// The files and folders the generator's behavior depends on.
+const CODE_PATHS: &'static [&'static str] = &["src", "eu4data/src", "Cargo.toml", "Cargo.lock"];
+
+fn main() {
+    let base = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
+
+    // FNV-1a, the same as the cache uses for everything else
+    let mut hash: u64 = 0xcbf29ce484222325;
+    let mut add = |bytes: &[u8]| for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    };
+
+    for code_path in CODE_PATHS {
+        let path = base.join(code_path);
+        if !path.exists() {
+            continue;
+        }
+        println!("cargo:rerun-if-changed={}", path.display());
+
+        let mut files = Vec::new();
+        list_files(&path, &mut files);
+        files.sort();
+        for file in files {
+            let mut data = Vec::new();
+            File::open(&file).unwrap().read_to_end(&mut data).unwrap();
+            add(file.strip_prefix(&base).unwrap().to_string_lossy().as_bytes());
+            add(&data);
+        }
+    }
+
+    println!("cargo:rustc-env=SHATTERED_CODE_HASH={:016x}", hash);
+}
+
+fn list_files(path: &Path, files: &mut Vec<PathBuf>) {
+    if path.is_dir() {
+        for entry in fs::read_dir(path).unwrap() {
+            list_files(&entry.unwrap().path(), files);
+        }
+    } else {
+        files.push(path.to_path_buf());
+    }
+}
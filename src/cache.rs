@@ -0,0 +1,138 @@
+//! A cache of hashes from the previous run, used to skip work for inputs that haven't changed.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use file;
+
+/// Identifies the generator's code, output from a different version is never re-used. The hash
+/// of the code is set by build.rs.
+pub const GENERATOR_VERSION: &'static str =
+    concat!(env!("CARGO_PKG_VERSION"), "+", env!("SHATTERED_CODE_HASH"));
+
+pub struct BuildCache {
+    path: Option<PathBuf>,
+    hashes: BTreeMap<String, u64>,
+}
+
+impl BuildCache {
+    /// Creates an empty cache that won't be saved, for runs that shouldn't use the cache.
+    pub fn disabled() -> Self {
+        BuildCache {
+            path: None,
+            hashes: BTreeMap::new(),
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Self {
+        let path = path.as_ref();
+        println!("Loading build cache at \"{}\"...", path.display());
+        let mut hashes = BTreeMap::new();
+
+        // Every line is a hash followed by the key it belongs to
+        if path.is_file() {
+            for line in file::read_all_text(path).lines() {
+                let mut split = line.splitn(2, ' ');
+                let hash = split.next().and_then(|h| u64::from_str_radix(h, 16).ok());
+                if let (Some(hash), Some(key)) = (hash, split.next()) {
+                    hashes.insert(key.to_string(), hash);
+                }
+            }
+        }
+
+        BuildCache {
+            path: Some(path.into()),
+            hashes: hashes,
+        }
+    }
+
+    pub fn save(&self) {
+        let path = match self.path {
+            Some(ref path) => path,
+            None => return,
+        };
+        println!("Saving build cache at \"{}\"...", path.display());
+
+        let mut text = String::new();
+        for (key, hash) in &self.hashes {
+            text.push_str(&format!("{:016x} {}\n", hash, key));
+        }
+        file::write_all_text(path, &text);
+    }
+
+    pub fn get(&self, key: &str) -> Option<u64> {
+        self.hashes.get(key).map(|h| *h)
+    }
+
+    /// Checks if the key was stored with this hash in the previous run.
+    pub fn is_unchanged(&self, key: &str, hash: u64) -> bool {
+        self.get(key) == Some(hash)
+    }
+
+    pub fn set(&mut self, key: &str, hash: u64) {
+        self.hashes.insert(key.into(), hash);
+    }
+
+    /// Removes all keys starting with the prefix, so stale entries don't stay around.
+    pub fn clear_prefix(&mut self, prefix: &str) {
+        let stale: Vec<_> = self.hashes.keys().filter(|k| k.starts_with(prefix)).cloned().collect();
+        for key in stale {
+            self.hashes.remove(&key);
+        }
+    }
+}
+
+/// A 64-bit FNV-1a hasher, unlike the standard library's hasher this gives the same hashes between
+/// runs. Values are fed to it through their `Hash` implementations, which can change between Rust
+/// versions, so a generator built with a different compiler may not re-use the previous output.
+pub struct ContentHasher(u64);
+
+impl ContentHasher {
+    pub fn new() -> Self {
+        ContentHasher(0xcbf29ce484222325)
+    }
+}
+
+impl Hasher for ContentHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+}
+
+pub fn hash_value<T: Hash + ?Sized>(value: &T) -> u64 {
+    let mut hasher = ContentHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+pub fn hash_file<P: AsRef<Path>>(path: P) -> u64 {
+    let mut data = Vec::new();
+    File::open(path).unwrap().read_to_end(&mut data).unwrap();
+    hash_value(&data[..])
+}
+
+/// Hashes a file or all files in a folder, including their paths.
+pub fn hash_path<P: AsRef<Path>>(path: P) -> u64 {
+    let path = path.as_ref();
+    let mut hasher = ContentHasher::new();
+
+    if path.is_dir() {
+        for relative in file::list_files(path) {
+            relative.hash(&mut hasher);
+            hash_file(path.join(&relative)).hash(&mut hasher);
+        }
+    } else if path.is_file() {
+        hash_file(path).hash(&mut hasher);
+    }
+
+    hasher.finish()
+}
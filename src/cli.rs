@@ -0,0 +1,132 @@
+use std::path::PathBuf;
+use clap::{App, Arg, ArgMatches, SubCommand};
+use shattered_generator::config::Config;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Command {
+    /// Generate the full mod.
+    Generate,
+    /// Check if the game data can be processed, without writing anything.
+    Validate,
+    /// Show which files in the existing mod would change when generating.
+    Diff,
+    /// Only regenerate the flags of the existing mod.
+    Flags,
+    /// Show statistics about the data that would be generated.
+    Stats,
+}
+
+pub struct Options {
+    pub command: Command,
+    pub config_path: PathBuf,
+    pub game_path: Option<PathBuf>,
+    pub target_path: Option<PathBuf>,
+    pub seed: Option<u64>,
+    pub dry_run: bool,
+    pub force: bool,
+    pub no_cache: bool,
+}
+
+impl Options {
+    pub fn parse() -> Self {
+        let matches = App::new("Shattered Generator")
+            .about("Generates the Shattered Universalis mod for Europa Universalis 4")
+            .arg(Arg::with_name("config")
+                .long("config")
+                .value_name("PATH")
+                .help("The config file to use, defaults to ./config/Config.toml")
+                .takes_value(true)
+                .global(true))
+            .arg(Arg::with_name("game-path")
+                .long("game-path")
+                .value_name("PATH")
+                .help("Overrides game_path in the config")
+                .takes_value(true)
+                .global(true))
+            .arg(Arg::with_name("target-path")
+                .long("target-path")
+                .value_name("PATH")
+                .help("Overrides target_path in the config")
+                .takes_value(true)
+                .global(true))
+            .arg(Arg::with_name("seed")
+                .long("seed")
+                .value_name("SEED")
+                .help("Overrides seed in the config")
+                .takes_value(true)
+                .validator(|v| parse_seed(&v).map(|_| ()))
+                .global(true))
+            .arg(Arg::with_name("dry-run")
+                .long("dry-run")
+                .help("Runs the generator without writing anything to disk")
+                .global(true))
+            .arg(Arg::with_name("force")
+                .long("force")
+                .help("Overwrites the target folder even if it wasn't generated by this tool")
+                .global(true))
+            .arg(Arg::with_name("no-cache")
+                .long("no-cache")
+                .help("Ignores the build cache and regenerates everything")
+                .global(true))
+            .subcommand(SubCommand::with_name("generate")
+                .about("Generates the full mod (default)"))
+            .subcommand(SubCommand::with_name("validate")
+                .about("Checks if the game data can be processed, without writing anything"))
+            .subcommand(SubCommand::with_name("diff")
+                .about("Shows which files in the existing mod would change when generating"))
+            .subcommand(SubCommand::with_name("flags")
+                .about("Only regenerates the flags of the existing mod"))
+            .subcommand(SubCommand::with_name("stats")
+                .about("Shows statistics about the data that would be generated"))
+            .get_matches();
+
+        let (command, sub_matches) = match matches.subcommand() {
+            ("validate", sub) => (Command::Validate, sub),
+            ("diff", sub) => (Command::Diff, sub),
+            ("flags", sub) => (Command::Flags, sub),
+            ("stats", sub) => (Command::Stats, sub),
+            (_, sub) => (Command::Generate, sub),
+        };
+
+        // Global arguments can be given both before and after the subcommand
+        let args = sub_matches.unwrap_or(&matches);
+        Options::from_matches(command, args)
+    }
+
+    fn from_matches(command: Command, args: &ArgMatches) -> Self {
+        // The seed has already been checked by its validator
+        let seed = args.value_of("seed").map(|v| parse_seed(v).unwrap());
+
+        Options {
+            command: command,
+            config_path: args.value_of("config").unwrap_or("./config/Config.toml").into(),
+            game_path: args.value_of("game-path").map(|v| v.into()),
+            target_path: args.value_of("target-path").map(|v| v.into()),
+            seed: seed,
+            dry_run: args.is_present("dry-run"),
+            force: args.is_present("force"),
+            no_cache: args.is_present("no-cache"),
+        }
+    }
+
+    /// Overrides values in the config with the ones given on the command line.
+    pub fn apply_to(&self, config: &mut Config) {
+        if let Some(ref game_path) = self.game_path {
+            config.game_path = Some(game_path.clone());
+        }
+        if let Some(ref target_path) = self.target_path {
+            config.target_path = Some(target_path.clone());
+        }
+        if self.seed.is_some() {
+            config.seed = self.seed;
+        }
+    }
+}
+
+/// Parses a seed from the command line, which has the same range as seeds in the config.
+fn parse_seed(value: &str) -> Result<u64, String> {
+    match value.parse::<i64>() {
+        Ok(seed) if seed >= 0 => Ok(seed as u64),
+        _ => Err(format!("Seed \"{}\" should be a number from 0 to {}", value, i64::MAX)),
+    }
+}
@@ -1,29 +1,561 @@
-use std::path::PathBuf;
-use toml::Parser;
-use file;
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use toml::{Parser, Table, Value};
+use detect;
+use output::OutputFormat;
+use passes::{self, CorePolicy, NativePolicy};
 
+const KNOWN_KEYS: &'static [&'static str] = &[
+    "mod_name", "mod_tags", "replace_paths", "target_path", "game_path", "tag_map_path", "seed",
+    "output_format", "cache_path", "passes",
+    "scripts", "shatter_areas", "shatter_regions", "shatter_superregions", "shatter_tags",
+    "exclude_tags", "cluster_size", "cluster_by",
+    "natives", "natives_by_superregion", "other_cores", "keep_old_owner_cores",
+    "compatriot_claims", "technology_groups", "unit_types", "generate_heirs", "generate_queens",
+];
+const PLACEHOLDER: &'static str = "<REPLACE WITH";
+
+#[derive(Clone, Hash)]
 pub struct Config {
-    pub mod_name: PathBuf,
-    pub target_path: PathBuf,
-    pub game_path: PathBuf,
+    pub mod_name: String,
+    pub mod_tags: Vec<String>,
+    pub replace_paths: Vec<String>,
+    /// The paths are detected by `validate` if they aren't set in the config or on the command
+    /// line.
+    pub target_path: Option<PathBuf>,
+    pub game_path: Option<PathBuf>,
+    pub tag_map_path: PathBuf,
+    pub seed: Option<u64>,
+    pub output_format: OutputFormat,
+    pub cache_path: PathBuf,
+    pub passes: Vec<String>,
+    pub scripts: Vec<PathBuf>,
+    /// If not empty, only provinces in these areas, regions or superregions are shattered.
+    pub shatter_areas: Vec<String>,
+    pub shatter_regions: Vec<String>,
+    pub shatter_superregions: Vec<String>,
+    /// If not empty, only provinces owned by these tags are shattered.
+    pub shatter_tags: Vec<String>,
+    pub exclude_tags: Vec<String>,
+    /// How many provinces new countries start with at most.
+    pub cluster_size: usize,
+    /// What provinces need to have in common to be clustered, see `passes::CLUSTER_RULES`.
+    pub cluster_by: Vec<String>,
+    pub natives: NativePolicy,
+    /// Overrides what's done with natives for specific superregions.
+    pub natives_by_superregion: BTreeMap<String, NativePolicy>,
+    /// What happens to the cores other countries have on shattered provinces.
+    pub other_cores: CorePolicy,
+    pub keep_old_owner_cores: bool,
+    /// If countries that used to be part of the same country get permanent claims on the
+    /// provinces they border.
+    pub compatriot_claims: bool,
+    /// Overrides the technology group of new countries, by culture or culture group.
+    pub technology_groups: BTreeMap<String, String>,
+    /// Overrides the unit type of new countries, by culture or culture group.
+    pub unit_types: BTreeMap<String, String>,
+    /// If new countries get an heir and a queen along with their ruler.
+    pub generate_heirs: bool,
+    pub generate_queens: bool,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    /// The config file could not be read.
+    Io(PathBuf, io::Error),
+    /// The config file is not valid TOML.
+    Parse(Vec<String>),
+    /// The config file is valid TOML, but the values in it are not.
+    Invalid(Vec<String>),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let problems = match self {
+            &ConfigError::Io(ref path, ref error) =>
+                return write!(f, "Could not read config at \"{}\": {}", path.display(), error),
+            &ConfigError::Parse(ref problems) => {
+                writeln!(f, "Config file is not valid TOML:")?;
+                problems
+            },
+            &ConfigError::Invalid(ref problems) => {
+                writeln!(f, "Config file contains errors:")?;
+                problems
+            },
+        };
+
+        for problem in problems {
+            writeln!(f, " - {}", problem)?;
+        }
+        Ok(())
+    }
 }
 
 impl Config {
-    pub fn load() -> Self {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
         println!("=== loading config ===");
-        println!("Loading config at \"./config/Config.toml\"...");
-        let toml = file::read_all_text("./config/Config.toml");
+        println!("Loading config at \"{}\"...", path.display());
+        let mut toml = String::new();
+        File::open(path)
+            .and_then(|mut f| f.read_to_string(&mut toml))
+            .map_err(|e| ConfigError::Io(path.into(), e))?;
 
         println!("Parsing config file...");
-        let values = Parser::new(&toml).parse().unwrap();
+        let mut parser = Parser::new(&toml);
+        let values = match parser.parse() {
+            Some(values) => values,
+            None => {
+                let problems = parser.errors.iter().map(|e| {
+                    let (line, col) = parser.to_linecol(e.lo);
+                    format!("{}:{}: {}", line + 1, col + 1, e.desc)
+                }).collect();
+                return Err(ConfigError::Parse(problems));
+            }
+        };
+
+        // Gather up all problems so the user can fix them in one go
+        let mut problems = Vec::new();
+        for key in values.keys() {
+            if !KNOWN_KEYS.contains(&key.as_str()) {
+                problems.push(match closest_key(key) {
+                    Some(known) => format!("Unknown key \"{}\", did you mean \"{}\"?", key, known),
+                    None => format!("Unknown key \"{}\"", key),
+                });
+            }
+        }
+
+        let mod_name = get_string(&values, "mod_name", &mut problems);
+        let mod_tags = get_string_list(&values, "mod_tags", &mut problems);
+        let replace_paths = get_string_list(&values, "replace_paths", &mut problems);
+        let target_path = get_optional_path(&values, "target_path", &mut problems);
+        let game_path = get_optional_path(&values, "game_path", &mut problems);
+        let tag_map_path = get_optional_path(&values, "tag_map_path", &mut problems)
+            .unwrap_or("./config/tag_map.txt".into());
+        let cache_path = get_optional_path(&values, "cache_path", &mut problems)
+            .unwrap_or("./config/build_cache.txt".into());
+        let seed = match values.get("seed") {
+            Some(&Value::Integer(seed)) if seed >= 0 => Some(seed as u64),
+            Some(_) => {
+                problems.push("Key \"seed\" should be a number of at least 0".into());
+                None
+            },
+            None => None,
+        };
+        let passes = if values.contains_key("passes") {
+            get_string_list(&values, "passes", &mut problems)
+        } else {
+            passes::DEFAULT_PASSES.iter().map(|p| p.to_string()).collect()
+        };
+        for pass in &passes {
+            if !passes::DEFAULT_PASSES.contains(&pass.as_str()) {
+                problems.push(format!("Unknown pass \"{}\" in \"passes\"", pass));
+            }
+        }
+        let scripts = get_string_list(&values, "scripts", &mut problems).iter()
+            .filter_map(|script| match expand_path(script) {
+                Ok(path) => Some(path),
+                Err(error) => {
+                    problems.push(format!("Key \"scripts\": {}", error));
+                    None
+                }
+            })
+            .collect();
+        let shatter_areas = get_string_list(&values, "shatter_areas", &mut problems);
+        let shatter_regions = get_string_list(&values, "shatter_regions", &mut problems);
+        let shatter_superregions = get_string_list(&values, "shatter_superregions", &mut problems);
+        let shatter_tags = get_string_list(&values, "shatter_tags", &mut problems);
+        let exclude_tags = get_string_list(&values, "exclude_tags", &mut problems);
+        let cluster_size = match values.get("cluster_size") {
+            Some(&Value::Integer(size)) if size >= 1 => size as usize,
+            Some(_) => {
+                problems.push("Key \"cluster_size\" should be a number of at least 1".into());
+                1
+            },
+            None => 1,
+        };
+        let cluster_by = if values.contains_key("cluster_by") {
+            get_string_list(&values, "cluster_by", &mut problems)
+        } else {
+            vec!["owner".to_string()]
+        };
+        for rule in &cluster_by {
+            if !passes::CLUSTER_RULES.contains(&rule.as_str()) {
+                problems.push(format!(
+                    "Unknown rule \"{}\" in \"cluster_by\", it should be one of {}",
+                    rule, passes::CLUSTER_RULES.join(", ")));
+            }
+        }
+        let natives = match values.get("natives") {
+            Some(value) => get_native_policy(value, "natives", &mut problems),
+            None => NativePolicy::Keep,
+        };
+        let mut natives_by_superregion = BTreeMap::new();
+        match values.get("natives_by_superregion") {
+            Some(&Value::Table(ref table)) => for (superregion, value) in table {
+                let policy = get_native_policy(value, "natives_by_superregion", &mut problems);
+                natives_by_superregion.insert(superregion.clone(), policy);
+            },
+            Some(_) => problems.push(
+                "Key \"natives_by_superregion\" should be a table of superregions".into()),
+            None => {},
+        }
+        let other_cores = match values.get("other_cores") {
+            Some(&Value::String(ref value)) if CorePolicy::parse(value).is_some() =>
+                CorePolicy::parse(value).unwrap(),
+            Some(_) => {
+                problems.push(
+                    "Key \"other_cores\" should be \"keep\", \"claims\" or \"remove\"".into());
+                CorePolicy::Claims
+            },
+            None => CorePolicy::Claims,
+        };
+        let keep_old_owner_cores = get_bool(&values, "keep_old_owner_cores", false, &mut problems);
+        let compatriot_claims = get_bool(&values, "compatriot_claims", false, &mut problems);
+        let technology_groups = get_string_table(&values, "technology_groups", &mut problems);
+        let unit_types = get_string_table(&values, "unit_types", &mut problems);
+        let generate_heirs = get_bool(&values, "generate_heirs", false, &mut problems);
+        let generate_queens = get_bool(&values, "generate_queens", false, &mut problems);
+        let output_format = match values.get("output_format") {
+            Some(&Value::String(ref format)) if format == "directory" => OutputFormat::Directory,
+            Some(&Value::String(ref format)) if format == "zip" => OutputFormat::Zip,
+            Some(_) => {
+                problems.push("Key \"output_format\" should be \"directory\" or \"zip\"".into());
+                OutputFormat::Directory
+            },
+            None => OutputFormat::Directory,
+        };
+
+        if problems.len() != 0 {
+            return Err(ConfigError::Invalid(problems));
+        }
 
         let config = Config {
-            mod_name: values["mod_name"].as_str().unwrap().into(),
-            target_path: values["target_path"].as_str().unwrap().into(),
-            game_path: values["game_path"].as_str().unwrap().into(),
+            mod_name: mod_name.unwrap(),
+            mod_tags: mod_tags,
+            replace_paths: replace_paths,
+            target_path: target_path,
+            game_path: game_path,
+            tag_map_path: tag_map_path,
+            seed: seed,
+            output_format: output_format,
+            cache_path: cache_path,
+            passes: passes,
+            scripts: scripts,
+            shatter_areas: shatter_areas,
+            shatter_regions: shatter_regions,
+            shatter_superregions: shatter_superregions,
+            shatter_tags: shatter_tags,
+            exclude_tags: exclude_tags,
+            cluster_size: cluster_size,
+            cluster_by: cluster_by,
+            natives: natives,
+            natives_by_superregion: natives_by_superregion,
+            other_cores: other_cores,
+            keep_old_owner_cores: keep_old_owner_cores,
+            compatriot_claims: compatriot_claims,
+            technology_groups: technology_groups,
+            unit_types: unit_types,
+            generate_heirs: generate_heirs,
+            generate_queens: generate_queens,
         };
 
         println!("");
-        config
+        Ok(config)
+    }
+
+    /// Checks if the config's values can actually be used, this is separate from loading so it
+    /// can be done after values have been overridden. Paths that still aren't set are detected.
+    pub fn validate(&mut self) -> Result<(), ConfigError> {
+        let mut problems = Vec::new();
+
+        detect_if_missing(
+            &mut self.target_path, "target_path", detect::find_target_path, &mut problems);
+        detect_if_missing(
+            &mut self.game_path, "game_path", detect::find_game_path, &mut problems);
+
+        let paths = [
+            ("target_path", self.target_path.as_ref()),
+            ("game_path", self.game_path.as_ref()),
+            ("tag_map_path", Some(&self.tag_map_path)),
+            ("cache_path", Some(&self.cache_path)),
+        ];
+        for &(key, path) in &paths {
+            if path.map(|p| p.to_string_lossy().contains(PLACEHOLDER)).unwrap_or(false) {
+                problems.push(format!(
+                    "Key \"{}\" still contains the placeholder from the template, replace it with \
+                     the actual path", key));
+            }
+        }
+
+        // If the country tags aren't there, this is definitely not a valid game folder
+        if let Some(ref game_path) = self.game_path {
+            if !game_path.join("common").join("country_tags").is_dir() {
+                problems.push(format!(
+                    "Game path \"{}\" does not look like a Europa Universalis 4 installation, \
+                     common/country_tags is missing", game_path.display()));
+            }
+        }
+
+        if problems.len() != 0 {
+            Err(ConfigError::Invalid(problems))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// The folder the mod is written to, only missing if it's not set and `validate` hasn't
+    /// detected it.
+    pub fn target_path(&self) -> &PathBuf {
+        self.target_path.as_ref().expect("target_path is not set, validate the config first")
+    }
+
+    /// The game's install folder, only missing if it's not set and `validate` hasn't detected it.
+    pub fn game_path(&self) -> &PathBuf {
+        self.game_path.as_ref().expect("game_path is not set, validate the config first")
+    }
+}
+
+fn get_string(values: &Table, key: &str, problems: &mut Vec<String>) -> Option<String> {
+    match values.get(key) {
+        Some(&Value::String(ref value)) => Some(value.clone()),
+        Some(_) => {
+            problems.push(format!("Key \"{}\" should be a string", key));
+            None
+        },
+        None => {
+            problems.push(format!("Missing required key \"{}\"", key));
+            None
+        },
+    }
+}
+
+fn get_string_list(values: &Table, key: &str, problems: &mut Vec<String>) -> Vec<String> {
+    let array = match values.get(key) {
+        Some(&Value::Array(ref array)) => array,
+        Some(_) => {
+            problems.push(format!("Key \"{}\" should be a list of strings", key));
+            return Vec::new();
+        },
+        None => return Vec::new(),
+    };
+
+    array.iter().filter_map(|value| match value {
+        &Value::String(ref value) => Some(value.clone()),
+        _ => {
+            problems.push(format!("Key \"{}\" should only contain strings", key));
+            None
+        },
+    }).collect()
+}
+
+fn get_string_table(
+    values: &Table, key: &str, problems: &mut Vec<String>
+) -> BTreeMap<String, String> {
+    let table = match values.get(key) {
+        Some(&Value::Table(ref table)) => table,
+        Some(_) => {
+            problems.push(format!("Key \"{}\" should be a table of strings", key));
+            return BTreeMap::new();
+        },
+        None => return BTreeMap::new(),
+    };
+
+    table.iter().filter_map(|(name, value)| match value {
+        &Value::String(ref value) => Some((name.clone(), value.clone())),
+        _ => {
+            problems.push(format!("Key \"{}\" should only contain strings", key));
+            None
+        },
+    }).collect()
+}
+
+fn get_bool(values: &Table, key: &str, default: bool, problems: &mut Vec<String>) -> bool {
+    match values.get(key) {
+        Some(&Value::Boolean(value)) => value,
+        Some(_) => {
+            problems.push(format!("Key \"{}\" should be true or false", key));
+            default
+        },
+        None => default,
+    }
+}
+
+fn get_native_policy(value: &Value, key: &str, problems: &mut Vec<String>) -> NativePolicy {
+    let policy = match value {
+        &Value::String(ref value) => NativePolicy::parse(value),
+        _ => None,
+    };
+    policy.unwrap_or_else(|| {
+        problems.push(format!(
+            "Key \"{}\" should be \"keep\", \"countries\" or \"remove\"", key));
+        NativePolicy::Keep
+    })
+}
+
+/// Attempts to detect a path that wasn't set in the config or on the command line.
+fn detect_if_missing<F: Fn() -> Option<PathBuf>>(
+    path: &mut Option<PathBuf>, key: &str, detect: F, problems: &mut Vec<String>
+) {
+    if path.is_some() {
+        return;
+    }
+
+    *path = detect();
+    match path {
+        &mut Some(ref path) => println!("Detected {} at \"{}\"", key, path.display()),
+        &mut None => problems.push(format!(
+            "Missing key \"{}\", it could not be detected automatically so it has to be set in \
+             the config or on the command line", key)),
+    }
+}
+
+fn get_optional_path(values: &Table, key: &str, problems: &mut Vec<String>) -> Option<PathBuf> {
+    match values.get(key) {
+        Some(&Value::String(ref value)) => match expand_path(value) {
+            Ok(path) => Some(path),
+            Err(error) => {
+                problems.push(format!("Key \"{}\": {}", key, error));
+                None
+            }
+        },
+        Some(_) => {
+            problems.push(format!("Key \"{}\" should be a string", key));
+            None
+        },
+        None => None,
+    }
+}
+
+/// Expands a leading ~ to the home directory and $VAR or ${VAR} to environment variables.
+fn expand_path(value: &str) -> Result<PathBuf, String> {
+    expand_path_with(value, |name| env::var(name).ok())
+}
+
+/// Expands the path like `expand_path`, getting the variables from the lookup instead of the
+/// environment.
+fn expand_path_with<F>(value: &str, lookup: F) -> Result<PathBuf, String>
+    where F: Fn(&str) -> Option<String>
+{
+    let lookup_var = |name: &str| lookup(name)
+        .ok_or_else(|| format!("Environment variable \"{}\" is not set", name));
+
+    let mut expanded = String::new();
+    let mut chars = value.chars().peekable();
+
+    if value == "~" || value.starts_with("~/") {
+        expanded.push_str(&lookup_var("HOME")?);
+        chars.next();
+    }
+
+    while let Some(c) = chars.next() {
+        if c != '$' {
+            expanded.push(c);
+            continue;
+        }
+
+        // Read the variable's name, either in braces or until the first non-name character
+        let mut name = String::new();
+        if chars.peek() == Some(&'{') {
+            chars.next();
+            loop {
+                match chars.next() {
+                    Some('}') => break,
+                    Some(c) => name.push(c),
+                    None => return Err(format!("Unclosed \"${{\" in \"{}\"", value)),
+                }
+            }
+        } else {
+            while let Some(&c) = chars.peek() {
+                if !c.is_alphanumeric() && c != '_' {
+                    break;
+                }
+                name.push(c);
+                chars.next();
+            }
+        }
+
+        if name.len() == 0 {
+            expanded.push('$');
+        } else {
+            expanded.push_str(&lookup_var(&name)?);
+        }
+    }
+
+    Ok(expanded.into())
+}
+
+/// Finds the known key closest to the given unknown key, if any is close enough to be a typo.
+fn closest_key(key: &str) -> Option<&'static str> {
+    KNOWN_KEYS.iter()
+        .map(|known| (*known, edit_distance(key, known)))
+        .filter(|&(_, distance)| distance <= 3)
+        .min_by_key(|&(_, distance)| distance)
+        .map(|(known, _)| known)
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..b.len() + 1).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + if ca == *cb { 0 } else { 1 };
+            current.push(*[previous[j + 1] + 1, current[j] + 1, substitution].iter().min().unwrap());
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use super::{expand_path_with, closest_key, detect_if_missing};
+
+    fn test_var(name: &str) -> Option<String> {
+        match name {
+            "HOME" => Some("/home/layl".to_string()),
+            "GAMES" => Some("/games".to_string()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn expand_home() {
+        assert_eq!(expand_path_with("~/mods", test_var).unwrap(), PathBuf::from("/home/layl/mods"));
+        assert_eq!(expand_path_with("/opt/~/mods", test_var).unwrap(), PathBuf::from("/opt/~/mods"));
+    }
+
+    #[test]
+    fn expand_variables() {
+        assert_eq!(expand_path_with("$GAMES/eu4", test_var).unwrap(), PathBuf::from("/games/eu4"));
+        assert_eq!(expand_path_with("${GAMES}eu4", test_var).unwrap(), PathBuf::from("/gameseu4"));
+        assert!(expand_path_with("$UNSET/eu4", test_var).is_err());
+    }
+
+    #[test]
+    fn detect_only_missing_paths() {
+        let mut problems = Vec::new();
+        let mut path = Some(PathBuf::from("/games/eu4"));
+        detect_if_missing(&mut path, "game_path", || panic!("Detected a path that was set"),
+            &mut problems);
+        assert_eq!(path, Some(PathBuf::from("/games/eu4")));
+
+        let mut path = None;
+        detect_if_missing(&mut path, "game_path", || None, &mut problems);
+        assert_eq!(problems.len(), 1);
+    }
+
+    #[test]
+    fn suggest_typos() {
+        assert_eq!(closest_key("gmae_path"), Some("game_path"));
+        assert_eq!(closest_key("target"), None);
     }
 }
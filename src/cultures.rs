@@ -0,0 +1,135 @@
+use std::collections::HashMap;
+use eu4data::{Eu4Table, Eu4Value};
+use FileTable;
+
+/// Keys in a culture group that aren't cultures.
+const GROUP_KEYS: &'static [&'static str] = &[
+    "graphical_culture", "second_graphical_culture", "male_names", "female_names", "dynasty_names",
+];
+
+/// The lists of names cultures and culture groups can have.
+pub const NAME_KEYS: &'static [&'static str] = &["male_names", "female_names", "dynasty_names"];
+
+/// The cultures from the game's common/cultures folder.
+pub struct Cultures {
+    /// The group of every culture.
+    groups: HashMap<String, String>,
+    /// The names of every culture and culture group, by its name and the kind of names.
+    names: HashMap<(String, String), Vec<String>>,
+    /// The graphical culture of every culture group, and of cultures that have their own.
+    graphics: HashMap<String, String>,
+}
+
+impl Cultures {
+    pub fn new(files: &[FileTable]) -> Self {
+        let mut groups = HashMap::new();
+        let mut names = HashMap::new();
+        let mut graphics = HashMap::new();
+
+        for group in files.iter().flat_map(|f| &f.data.values) {
+            let cultures = match &group.value {
+                &Eu4Value::Table(ref table) => table,
+                _ => continue,
+            };
+            add_names(&mut names, &group.key, cultures);
+            add_graphics(&mut graphics, &group.key, cultures);
+
+            // Cultures are the tables in a group, next to the group's own names and graphics
+            for culture in &cultures.values {
+                if let &Eu4Value::Table(ref table) = &culture.value {
+                    if !GROUP_KEYS.contains(&culture.key.as_str()) {
+                        groups.insert(culture.key.clone(), group.key.clone());
+                        add_names(&mut names, &culture.key, table);
+                        add_graphics(&mut graphics, &culture.key, table);
+                    }
+                }
+            }
+        }
+
+        Cultures {
+            groups: groups,
+            names: names,
+            graphics: graphics,
+        }
+    }
+
+    pub fn group(&self, culture: &str) -> Option<&str> {
+        self.groups.get(culture).map(|g| g.as_str())
+    }
+
+    /// Gets the names of a kind from `NAME_KEYS` a culture has, or its group has if the culture
+    /// doesn't have any.
+    pub fn names(&self, culture: &str, kind: &str) -> Vec<&str> {
+        let own = self.names.get(&(culture.to_string(), kind.to_string()));
+        let group = self.group(culture)
+            .and_then(|g| self.names.get(&(g.to_string(), kind.to_string())));
+
+        own.or(group)
+            .map(|names| names.iter().map(|n| n.as_str()).collect())
+            .unwrap_or_else(Vec::new)
+    }
+
+    /// Gets the graphical culture of a culture, or its group's if it doesn't have its own.
+    pub fn graphical_culture(&self, culture: &str) -> Option<&str> {
+        self.graphics.get(culture)
+            .or_else(|| self.group(culture).and_then(|g| self.graphics.get(g)))
+            .map(|g| g.as_str())
+    }
+}
+
+/// Adds the graphical culture of a culture or culture group's table, if it has one.
+fn add_graphics(graphics: &mut HashMap<String, String>, name: &str, table: &Eu4Table) {
+    if let Some(&Eu4Value::String(ref value)) = table.get("graphical_culture") {
+        graphics.insert(name.to_string(), value.clone());
+    }
+}
+
+/// Adds the lists of names in a culture or culture group's table.
+fn add_names(names: &mut HashMap<(String, String), Vec<String>>, name: &str, table: &Eu4Table) {
+    for kind in NAME_KEYS {
+        let values: Vec<String> = match table.get(kind) {
+            Some(&Eu4Value::Array(ref values)) => values.iter()
+                .filter_map(|v| match v {
+                    &Eu4Value::String(ref value) => Some(value.clone()),
+                    _ => None,
+                })
+                .collect(),
+            _ => continue,
+        };
+
+        if values.len() != 0 {
+            names.insert((name.to_string(), kind.to_string()), values);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use eu4data::Eu4Table;
+    use FileTable;
+    use super::Cultures;
+
+    #[test]
+    fn culture_groups() {
+        let file = FileTable {
+            file_name: "00_cultures.txt".into(),
+            data: Eu4Table::parse(
+                "scandinavian = {\n\
+                 graphical_culture = scandinaviangfx\n\
+                 male_names = { Karl Erik }\n\
+                 swedish = { primary = SWE male_names = { Gustav } }\n\
+                 danish = { primary = DAN }\n\
+                 }"),
+        };
+        let cultures = Cultures::new(&[file]);
+
+        assert_eq!(cultures.group("danish"), Some("scandinavian"));
+        assert_eq!(cultures.group("male_names"), None);
+        assert_eq!(cultures.group("austrian"), None);
+        assert_eq!(cultures.names("swedish", "male_names"), vec!["Gustav"]);
+        assert_eq!(cultures.names("danish", "male_names"), vec!["Karl", "Erik"]);
+        assert!(cultures.names("danish", "female_names").is_empty());
+        assert_eq!(cultures.graphical_culture("swedish"), Some("scandinaviangfx"));
+        assert_eq!(cultures.graphical_culture("austrian"), None);
+    }
+}
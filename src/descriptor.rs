@@ -0,0 +1,178 @@
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use config::Config;
+use file;
+use output::{self, OutputError, OutputFormat};
+
+/// Writes the descriptor.mod file inside of the mod being built.
+pub fn write(config: &Config, build_path: &Path) {
+    println!("Generating mod descriptor...");
+    if detect_game_version(config.game_path()).is_none() {
+        println!("Could not detect the game version, the mod won't be marked as supported");
+    }
+
+    file::write_all_text(build_path.join("descriptor.mod"), &generate_with_version(config));
+}
+
+/// Writes the .mod file next to the target that the launcher uses to find the mod, this should
+/// only be done once the mod itself is in place.
+pub fn write_outer(config: &Config) {
+    let outer_path = get_outer_path(config.target_path());
+    println!("Writing launcher descriptor \"{}\"...", outer_path.display());
+
+    // The outer .mod file has the same data, but also needs to know where the mod is
+    let outer_descriptor = format!(
+        "{}{}\n", generate_with_version(config), get_location(config, config.output_format));
+    file::write_all_text(outer_path, &outer_descriptor);
+}
+
+/// Checks if we're allowed to overwrite the .mod file next to the target, which is the case if it
+/// points to the target or doesn't exist at all.
+pub fn check_owned(config: &Config, force: bool) -> Result<(), OutputError> {
+    let outer_path = get_outer_path(config.target_path());
+    if force || !outer_path.exists() {
+        return Ok(());
+    }
+
+    // We may have written it for the other format last time
+    let text = String::from_utf8_lossy(&fs::read(&outer_path).unwrap_or_else(|_| Vec::new()))
+        .into_owned();
+    let locations = [
+        get_location(config, OutputFormat::Directory),
+        get_location(config, OutputFormat::Zip),
+    ];
+    if text.lines().any(|line| locations.iter().any(|l| l == line.trim())) {
+        Ok(())
+    } else {
+        Err(OutputError::NotOwned(outer_path))
+    }
+}
+
+fn generate_with_version(config: &Config) -> String {
+    let supported_version = detect_game_version(config.game_path())
+        .map(|version| to_supported_version(&version));
+    generate(config, supported_version.as_ref().map(|v| v.as_str()))
+}
+
+fn get_outer_path(target_path: &Path) -> PathBuf {
+    let mod_folder_name = target_path.file_name().unwrap().to_str().unwrap();
+    target_path.with_file_name(format!("{}.mod", mod_folder_name))
+}
+
+/// Gets the line telling the launcher where the mod is.
+fn get_location(config: &Config, format: OutputFormat) -> String {
+    match format {
+        OutputFormat::Directory =>
+            format!("path = {}", quote(&get_launcher_path(config.target_path()))),
+        OutputFormat::Zip => format!("archive = {}",
+            quote(&get_launcher_path(&output::get_archive_path(config.target_path())))),
+    }
+}
+
+fn generate(config: &Config, supported_version: Option<&str>) -> String {
+    let mut text = String::new();
+
+    text.push_str(&format!("name = {}\n", quote(&config.mod_name)));
+    if config.mod_tags.len() != 0 {
+        text.push_str("tags = {\n");
+        for tag in &config.mod_tags {
+            text.push_str(&format!("\t{}\n", quote(tag)));
+        }
+        text.push_str("}\n");
+    }
+    for replace_path in &config.replace_paths {
+        text.push_str(&format!("replace_path = {}\n", quote(replace_path)));
+    }
+    if let Some(supported_version) = supported_version {
+        text.push_str(&format!("supported_version = {}\n", quote(supported_version)));
+    }
+
+    text
+}
+
+fn quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Gets the path the launcher should use, mods in the game's own mod folder are referred to
+/// relatively, mods elsewhere by their full path.
+fn get_launcher_path(target_path: &Path) -> String {
+    let mut components: Vec<_> = target_path.components().collect();
+    let folder = components.pop();
+
+    match (components.last(), folder) {
+        (Some(&Component::Normal(parent)), Some(Component::Normal(folder))) if parent == "mod" =>
+            format!("mod/{}", folder.to_str().unwrap()),
+        _ => target_path.to_str().unwrap().replace('\\', "/"),
+    }
+}
+
+/// Reads the game's version from the launcher settings.
+fn detect_game_version(game_path: &PathBuf) -> Option<String> {
+    let candidates = [
+        game_path.join("launcher-settings.json"),
+        game_path.join("launcher").join("launcher-settings.json"),
+    ];
+    let settings_file = match candidates.iter().find(|c| c.is_file()) {
+        Some(settings_file) => settings_file,
+        None => return None,
+    };
+    let settings = file::read_all_text(settings_file);
+
+    // We only need one value out of this file, so we look for it rather than parsing the JSON,
+    // rawVersion looks like "v1.30.6" and version like "1.30.6.0"
+    find_json_string(&settings, "rawVersion")
+        .or_else(|| find_json_string(&settings, "version"))
+        .map(|v| v.trim_start_matches('v').to_string())
+}
+
+fn find_json_string<'a>(json: &'a str, key: &str) -> Option<&'a str> {
+    let key_start = match json.find(&format!("\"{}\"", key)) {
+        Some(key_start) => key_start + key.len() + 2,
+        None => return None,
+    };
+
+    let rest = json[key_start..].trim_start();
+    if !rest.starts_with(':') {
+        return None;
+    }
+    let rest = rest[1..].trim_start();
+    if !rest.starts_with('"') {
+        return None;
+    }
+    let rest = &rest[1..];
+    rest.find('"').map(|end| &rest[..end])
+}
+
+/// Turns a full version into a version the launcher can match with any patch, like "1.30.*".
+fn to_supported_version(version: &str) -> String {
+    let segments: Vec<_> = version.split('.').take(2).collect();
+    format!("{}.*", segments.join("."))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use super::{find_json_string, get_launcher_path, to_supported_version};
+
+    #[test]
+    fn find_version_in_settings() {
+        let json = "{\n\t\"gameId\": \"eu4\",\n\t\"rawVersion\" : \"v1.30.6\",\n\t\"version\": \"x\"\n}";
+        assert_eq!(find_json_string(json, "rawVersion"), Some("v1.30.6"));
+        assert_eq!(find_json_string(json, "distPlatform"), None);
+    }
+
+    #[test]
+    fn supported_version_matches_patches() {
+        assert_eq!(to_supported_version("1.30.6.0"), "1.30.*");
+        assert_eq!(to_supported_version("1.16"), "1.16.*");
+    }
+
+    #[test]
+    fn launcher_path_relative_in_mod_folder() {
+        let in_mod = PathBuf::from("/home/layl/.local/share/Europa Universalis IV/mod/shattered");
+        assert_eq!(get_launcher_path(&in_mod), "mod/shattered");
+        let elsewhere = PathBuf::from("/tmp/shattered");
+        assert_eq!(get_launcher_path(&elsewhere), "/tmp/shattered");
+    }
+}
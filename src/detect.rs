@@ -0,0 +1,84 @@
+//! Detection of the game and mod folders, so they don't have to be set in the config. This
+//! currently only knows about the locations used on Linux.
+
+use std::env;
+use std::path::PathBuf;
+use file;
+use vdf::VdfValue;
+
+/// Finds the game's install folder by looking through all Steam library folders.
+pub fn find_game_path() -> Option<PathBuf> {
+    let home = match env::var("HOME") {
+        Ok(home) => PathBuf::from(home),
+        Err(_) => return None,
+    };
+
+    // Steam can be found in either of these, often one is a link to the other
+    let steam_roots = [
+        home.join(".steam").join("steam"),
+        home.join(".local").join("share").join("Steam"),
+    ];
+
+    for steam_root in &steam_roots {
+        for library in find_steam_libraries(steam_root) {
+            let game_path = library
+                .join("steamapps").join("common").join("Europa Universalis IV");
+            if game_path.join("common").join("country_tags").is_dir() {
+                return Some(game_path);
+            }
+        }
+    }
+
+    None
+}
+
+/// Finds the folder the generated mod should go in, inside the game's user mod folder.
+pub fn find_target_path() -> Option<PathBuf> {
+    let home = match env::var("HOME") {
+        Ok(home) => PathBuf::from(home),
+        Err(_) => return None,
+    };
+
+    let user_dir = home
+        .join(".local").join("share").join("Paradox Interactive").join("Europa Universalis IV");
+    if user_dir.is_dir() {
+        Some(user_dir.join("mod").join("shattered"))
+    } else {
+        None
+    }
+}
+
+fn find_steam_libraries(steam_root: &PathBuf) -> Vec<PathBuf> {
+    // The Steam folder itself is always a library
+    let mut libraries = vec![steam_root.clone()];
+
+    let library_folders = steam_root.join("steamapps").join("libraryfolders.vdf");
+    if !library_folders.is_file() {
+        return libraries;
+    }
+
+    let data = match VdfValue::parse(&file::read_all_text(&library_folders)) {
+        Ok(data) => data,
+        Err(error) => {
+            println!("Could not parse \"{}\": {}", library_folders.display(), error);
+            return libraries;
+        }
+    };
+
+    // Libraries are listed under numbered keys, older versions of Steam store the path
+    // directly while newer versions store a table with a path in it
+    if let Some(folders) = data.get("libraryfolders") {
+        for &(ref key, ref value) in folders.entries() {
+            if key.parse::<u32>().is_err() {
+                continue;
+            }
+
+            let path = value.as_str().or_else(|| value.get("path").and_then(|p| p.as_str()));
+            if let Some(path) = path {
+                libraries.push(PathBuf::from(path));
+            }
+        }
+    }
+
+    libraries
+}
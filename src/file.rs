@@ -1,5 +1,5 @@
-use std::path::Path;
-use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::fs::{self, File};
 use std::io::{Read, Write};
 use encoding::{Encoding, DecoderTrap, EncoderTrap};
 use encoding::all::WINDOWS_1252;
@@ -34,3 +34,76 @@ pub fn write_all_win_1252<P: AsRef<Path>>(path: P, text: &str) {
 
     file.write_all(&data).unwrap();
 }
+
+/// Lists all files in the directory and its subdirectories, relative to the directory.
+pub fn list_files<P: AsRef<Path>>(dir: P) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    if dir.as_ref().is_dir() {
+        list_files_into(dir.as_ref(), Path::new(""), &mut files);
+    }
+    files.sort();
+    files
+}
+
+/// Gets the game data files from the paths with their file names, sorted by file name so the
+/// order doesn't depend on the filesystem.
+pub fn sort_data_files(paths: Vec<PathBuf>) -> Vec<(String, PathBuf)> {
+    let mut files: Vec<_> = paths.into_iter()
+        .filter(|path| path.extension().map(|e| e == "txt").unwrap_or(false))
+        .map(|path| (path.file_name().unwrap().to_str().unwrap().to_string(), path))
+        .collect();
+    files.sort_by(|a, b| a.0.cmp(&b.0));
+    files
+}
+
+fn list_files_into(base: &Path, relative: &Path, files: &mut Vec<PathBuf>) {
+    for entry_r in base.join(relative).read_dir().unwrap() {
+        let entry = entry_r.unwrap();
+        let entry_relative = relative.join(entry.file_name());
+
+        if entry.path().is_dir() {
+            list_files_into(base, &entry_relative, files);
+        } else {
+            files.push(entry_relative);
+        }
+    }
+}
+
+pub fn same_contents<P: AsRef<Path>, Q: AsRef<Path>>(a: P, b: Q) -> bool {
+    let mut a_data = Vec::new();
+    File::open(a).unwrap().read_to_end(&mut a_data).unwrap();
+    let mut b_data = Vec::new();
+    File::open(b).unwrap().read_to_end(&mut b_data).unwrap();
+
+    a_data == b_data
+}
+
+/// Replaces the target with a hard link to the source, or a copy if linking isn't possible.
+pub fn replace_with_link<P: AsRef<Path>, Q: AsRef<Path>>(source: P, target: Q) {
+    let target = target.as_ref();
+    if target.exists() {
+        fs::remove_file(target).unwrap();
+    }
+
+    if fs::hard_link(&source, target).is_err() {
+        fs::copy(&source, target).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use super::sort_data_files;
+
+    #[test]
+    fn data_files_sorted_by_name() {
+        let paths = vec!["game/2 - Ostergotland.txt", "game/Thumbs.db", "game/12 - Skane.txt",
+            "game/3 - Kalmar.txt", "game/1 - Stockholm.txt", "game/readme"];
+        let files = sort_data_files(paths.into_iter().map(PathBuf::from).collect());
+
+        let names: Vec<_> = files.iter().map(|f| f.0.as_str()).collect();
+        assert_eq!(names,
+            vec!["1 - Stockholm.txt", "12 - Skane.txt", "2 - Ostergotland.txt", "3 - Kalmar.txt"]);
+        assert_eq!(files[0].1, PathBuf::from("game/1 - Stockholm.txt"));
+    }
+}
@@ -1,76 +1,165 @@
 use std::fs;
+use std::path::Path;
 use imagefmt::{self, ColFmt, ColType};
 use palette::{Rgb, Rgba};
 use palette::blend::PreAlpha;
 use rand::{Rng, StdRng};
+use rayon::prelude::*;
+use cache::{self, BuildCache};
 use config::Config;
-use Eu4TargetData;
+use file;
+use {Eu4TargetData, Eu4FlagRequest};
+
+/// The width and height of generated flags.
+const FLAG_SIZE: usize = 128;
+const AREA_PER_PIXEL: f32 = 1.0 / FLAG_SIZE as f32;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum FlagStyle {
+    Flat,
+    Dashed,
+    DashedInverted,
+    Crossed,
+    HorizontalLine,
+    VerticalLine,
+}
 
-pub fn generate(config: &Config, data: &Eu4TargetData) {
+/// Generates flags for all flag requests, flags that are unchanged since the previous run are
+/// taken from the previous output if there is one.
+pub fn generate(
+    config: &Config, data: &Eu4TargetData, previous: Option<&Path>, cache: &mut BuildCache
+) {
     println!("Generating flags...");
 
     // Set up the directory to output flags to
-    let mut flag_base = config.target_path.clone();
+    let mut flag_base = config.target_path().clone();
     flag_base.push("gfx");
     flag_base.push("flags");
     fs::create_dir_all(&flag_base).unwrap();
 
-    // Go over all requested flags
-    let mut rand = StdRng::new().unwrap();
-    for flag in &data.flag_requests {
+    // Go over all requested flags, every flag has its own seed so the order they're generated in
+    // doesn't matter and we can generate them in parallel
+    let previous_cache = &*cache;
+    let results: Vec<_> = data.flag_requests.par_iter().map(|flag| {
         let mut flag_file = flag_base.clone();
         flag_file.push(format!("{}.tga", flag.tag));
 
-        // Prepare some data to generate this flag
-        let flag_func = get_flag_function(&mut rand);
-
-        // Generate the image
-        let width = 128;
-        let area_per_pixel = 1.0 / width as f32;
-        let size = width*width;
-        let per_pixel = 3;
-        let mut buffer = vec![0u8; size*per_pixel];
-        for yi in 0..width {
-            for xi in 0..width {
-                // Calculate normalized coordinates
-                let x = (xi as f32) / (width as f32);
-                let y = (yi as f32) / (width as f32);
-
-                // Calculate this pixel's color
-                let color = flag_func(x, y, area_per_pixel, flag.color, flag.color_alt);
-
-                // Calculate and store the color for this pixel
-                let u8_color: [u8; 3] = color.to_pixel();
-                let actual = ((yi*width)+xi)*per_pixel;
-                buffer[actual+0] = u8_color[0];
-                buffer[actual+1] = u8_color[1];
-                buffer[actual+2] = u8_color[2];
+        // A flag only depends on its request, so if that's the same we can re-use the old flag
+        let key = format!("flag:{}", flag.tag);
+        let hash = hash_request(flag);
+        if let Some(previous) = previous {
+            let previous_file = previous.join("gfx").join("flags").join(format!("{}.tga", flag.tag));
+            if previous_cache.is_unchanged(&key, hash) && previous_file.is_file() {
+                if previous_file != flag_file {
+                    file::replace_with_link(&previous_file, &flag_file);
+                }
+                return (key, hash, true);
             }
         }
 
+        // Generate the image
+        let mut rand = flag.seed.rng();
+        let style = get_flag_style(&mut rand);
+        let buffer = render_flag(style, flag.color, flag.color_alt);
+
         // Write the image to a file
         imagefmt::write(
             &flag_file,
-            128, 128, ColFmt::RGB,
+            FLAG_SIZE, FLAG_SIZE, ColFmt::RGB,
             &buffer,
             ColType::Color
         ).unwrap();
+
+        (key, hash, false)
+    }).collect();
+
+    cache.clear_prefix("flag:");
+    let mut reused = 0;
+    for (key, hash, was_reused) in results {
+        cache.set(&key, hash);
+        if was_reused {
+            reused += 1;
+        }
     }
+
+    println!("Re-used {} unchanged flags", reused);
+}
+
+fn hash_request(flag: &Eu4FlagRequest) -> u64 {
+    let color_bits = |c: Rgb| [c.red.to_bits(), c.green.to_bits(), c.blue.to_bits()];
+    let values = (
+        cache::GENERATOR_VERSION, &flag.tag, color_bits(flag.color), color_bits(flag.color_alt),
+        flag.seed,
+    );
+    cache::hash_value(&values)
 }
 
-fn get_flag_function(rand: &mut StdRng) -> Box<Fn(f32, f32, f32, Rgb, Rgb) -> Rgba> {
+fn get_flag_style(rand: &mut StdRng) -> FlagStyle {
     let num: i32 = rand.gen_range(0, 6);
     match num {
-        0 => Box::new(func_flat_flag),
-        1 => Box::new(func_dashed_flag),
-        2 => Box::new(func_dashed_inverted_flag),
-        3 => Box::new(func_crossed_flag),
-        4 => Box::new(func_horizontal_line_flag),
-        5 => Box::new(func_vertical_line_flag),
+        0 => FlagStyle::Flat,
+        1 => FlagStyle::Dashed,
+        2 => FlagStyle::DashedInverted,
+        3 => FlagStyle::Crossed,
+        4 => FlagStyle::HorizontalLine,
+        5 => FlagStyle::VerticalLine,
         _ => panic!("Generated flag type out of range")
     }
 }
 
+/// Renders a flag into an RGB buffer.
+fn render_flag(style: FlagStyle, color: Rgb, color_alt: Rgb) -> Vec<u8> {
+    let row_size = FLAG_SIZE * 3;
+    let mut buffer = vec![0u8; FLAG_SIZE * row_size];
+
+    // Flags that don't change over the vertical axis only need one row calculated
+    if style == FlagStyle::VerticalLine {
+        let (first, rest) = buffer.split_at_mut(row_size);
+        render_row(style, 0, color, color_alt, first);
+        for row in rest.chunks_mut(row_size) {
+            row.copy_from_slice(first);
+        }
+        return buffer;
+    }
+
+    for (yi, row) in buffer.chunks_mut(row_size).enumerate() {
+        render_row(style, yi, color, color_alt, row);
+    }
+
+    buffer
+}
+
+/// Renders a single row of a flag into an RGB buffer.
+fn render_row(style: FlagStyle, yi: usize, color: Rgb, color_alt: Rgb, row: &mut [u8]) {
+    let y = (yi as f32) / (FLAG_SIZE as f32);
+
+    let flag_func: fn(f32, f32, f32, Rgb, Rgb) -> Rgba = match style {
+        // These don't change over the horizontal axis, so the whole row is one color
+        FlagStyle::Flat | FlagStyle::HorizontalLine => {
+            let func = if style == FlagStyle::Flat {
+                func_flat_flag
+            } else {
+                func_horizontal_line_flag
+            };
+            let pixel: [u8; 3] = func(0.0, y, AREA_PER_PIXEL, color, color_alt).to_pixel();
+            for chunk in row.chunks_mut(3) {
+                chunk.copy_from_slice(&pixel);
+            }
+            return;
+        },
+        FlagStyle::Dashed => func_dashed_flag,
+        FlagStyle::DashedInverted => func_dashed_inverted_flag,
+        FlagStyle::Crossed => func_crossed_flag,
+        FlagStyle::VerticalLine => func_vertical_line_flag,
+    };
+
+    for (xi, chunk) in row.chunks_mut(3).enumerate() {
+        let x = (xi as f32) / (FLAG_SIZE as f32);
+        let pixel: [u8; 3] = flag_func(x, y, AREA_PER_PIXEL, color, color_alt).to_pixel();
+        chunk.copy_from_slice(&pixel);
+    }
+}
+
 fn func_flat_flag(_x: f32, _y: f32, _area_per_pixel: f32, color: Rgb, _color_alt: Rgb) -> Rgba {
     flag_shader_flat(color)
 }
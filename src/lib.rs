@@ -0,0 +1,587 @@
+//! Generates the mod "Shattered Universalis" for Europa Universalis 4, which splits every
+//! province into its own country.
+//!
+//! The generator is a pipeline of stages, which can be used separately to make custom variants:
+//! game data is loaded with `load_eu4_data`, turned into the mod's data with `process_eu4_data`,
+//! and written out with `write_mod` or the individual writers. Processing is done by a list of
+//! passes from the `passes` module, which can be extended with passes of your own.
+
+extern crate encoding;
+extern crate imagefmt;
+extern crate palette;
+extern crate rand;
+extern crate rayon;
+extern crate rhai;
+extern crate toml;
+extern crate zip;
+extern crate eu4data;
+
+pub mod cache;
+pub mod config;
+pub mod cultures;
+pub mod descriptor;
+mod detect;
+pub mod file;
+pub mod flags;
+pub mod map;
+pub mod output;
+pub mod passes;
+pub mod regions;
+pub mod script;
+pub mod seed;
+pub mod tags;
+mod vdf;
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use palette::Rgb;
+use rayon::prelude::*;
+use cache::{BuildCache, ContentHasher};
+use config::Config;
+use cultures::Cultures;
+use output::{OutputError, OutputFormat};
+use map::Map;
+use passes::{Pass, PassContext};
+use regions::Regions;
+use script::ScriptError;
+use seed::Seed;
+use tags::TagMap;
+use eu4data::Eu4Table;
+
+/// The game files and folders the generator reads from.
+pub const INPUT_PATHS: &'static [&'static str] = &[
+    "history/provinces",
+    "history/countries",
+    "history/diplomacy",
+    "history/wars",
+    "common/cultures",
+    "common/countries",
+    "common/country_tags/00_countries.txt",
+    "localisation/countries_l_english.yml",
+    "map/area.txt",
+    "map/region.txt",
+    "map/superregion.txt",
+    "map/definition.csv",
+    "map/provinces.bmp",
+    "map/adjacencies.csv",
+    "launcher-settings.json",
+    "launcher/launcher-settings.json",
+];
+
+/// The game's localisation file the new countries' names are added to.
+pub const LOCALISATION_PATH: &'static str = "localisation/countries_l_english.yml";
+
+/// Hashes of what each stage of the pipeline depends on, so a stage can be skipped if they're the
+/// same as in the previous run.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StageHashes {
+    /// The game files and scripts that are loaded.
+    pub load: u64,
+    /// Everything processing depends on, the loaded files, the config, the seed and the tags.
+    pub process: u64,
+    /// Everything the written mod depends on, processing and how the mod is written.
+    pub output: u64,
+}
+
+impl StageHashes {
+    /// Hashes the inputs of every stage, this reads all game files so it's only done once a run.
+    pub fn new(config: &Config, tag_map: &TagMap, seed: Seed) -> Self {
+        let mut hasher = ContentHasher::new();
+        cache::GENERATOR_VERSION.hash(&mut hasher);
+        for input in INPUT_PATHS {
+            cache::hash_path(config.game_path().join(input)).hash(&mut hasher);
+        }
+        for script in &config.scripts {
+            cache::hash_path(script).hash(&mut hasher);
+        }
+
+        StageHashes::from_load(hasher.finish(), config, tag_map, seed)
+    }
+
+    /// Updates the hashes with the tags assigned while processing, without reading the game
+    /// files again.
+    pub fn with_tag_map(&self, config: &Config, tag_map: &TagMap, seed: Seed) -> Self {
+        StageHashes::from_load(self.load, config, tag_map, seed)
+    }
+
+    fn from_load(load: u64, config: &Config, tag_map: &TagMap, seed: Seed) -> Self {
+        // Settings for how the mod is written don't change what processing does, everything
+        // else is included so new settings can't be forgotten
+        let mut processing_config = config.clone();
+        processing_config.mod_name = String::new();
+        processing_config.mod_tags = Vec::new();
+        processing_config.replace_paths = Vec::new();
+        processing_config.target_path = None;
+        processing_config.tag_map_path = PathBuf::new();
+        processing_config.output_format = OutputFormat::Directory;
+        processing_config.cache_path = PathBuf::new();
+
+        let process = cache::hash_value(&(load, &processing_config, seed, tag_map.serialize()));
+        StageHashes {
+            load: load,
+            process: process,
+            output: cache::hash_value(&(process, config)),
+        }
+    }
+}
+
+/// Loads the game data and processes it, the first two stages of the pipeline.
+pub fn load_and_process(
+    config: &Config, tag_map: &mut TagMap, seed: Seed
+) -> Result<Eu4TargetData, ScriptError> {
+    let passes = passes::get_passes(config)?;
+    let source_data = load_eu4_data(config);
+    Ok(process_eu4_data(source_data, &passes, tag_map, seed))
+}
+
+/// Writes the full mod to the target in the configured format, the target is only replaced once
+/// the mod has been fully written.
+pub fn write_mod(
+    config: &Config, target_data: &Eu4TargetData, force: bool, cache: &mut BuildCache
+) -> Result<(), OutputError> {
+    let build_path = prepare_build(config, force)?;
+
+    // Everything gets written to the build folder, and only moved to the target when done
+    let mut build_config = config.clone();
+    build_config.target_path = Some(build_path.clone());
+
+    // Generate the descriptor the launcher needs, the one it finds the mod with comes last
+    descriptor::write(config, &build_path);
+    println!("");
+
+    write_eu4_data(&build_config, target_data);
+
+    // Slow or unchanged parts can be re-used from the previous mod folder, if we have one
+    let previous = if config.output_format == OutputFormat::Directory {
+        Some(config.target_path().as_path())
+    } else {
+        None
+    };
+
+    println!("=== generating polish data ===");
+    let localisation_hash = hash_localisation(config, target_data);
+    let previous_localisation = previous.map(|p| p.join(LOCALISATION_PATH));
+    match previous_localisation {
+        Some(ref previous_localisation)
+            if cache.is_unchanged("localisation", localisation_hash) &&
+                previous_localisation.is_file() => {
+            println!("Re-used the unchanged country localisation");
+            let target_localisation = build_path.join(LOCALISATION_PATH);
+            fs::create_dir_all(target_localisation.parent().unwrap()).unwrap();
+            file::replace_with_link(previous_localisation, target_localisation);
+        },
+        _ => write_eu4_localisation(&build_config, target_data),
+    }
+    cache.set("localisation", localisation_hash);
+
+    flags::generate(&build_config, target_data, previous, cache);
+    println!("");
+
+    finish_build(config, &build_path, cache);
+    Ok(())
+}
+
+/// Writes the mod again from the previous output with new descriptors, for when only how the mod
+/// is written changed since the previous run. The previous output has to be in the configured
+/// format.
+pub fn rewrite_mod(config: &Config, force: bool, cache: &mut BuildCache)
+    -> Result<(), OutputError> {
+    let build_path = prepare_build(config, force)?;
+
+    println!("Copying the previous output...");
+    match config.output_format {
+        OutputFormat::Directory => for relative in file::list_files(config.target_path()) {
+            let target_file = build_path.join(&relative);
+            fs::create_dir_all(target_file.parent().unwrap()).unwrap();
+            fs::copy(config.target_path().join(&relative), target_file).unwrap();
+        },
+        OutputFormat::Zip => output::extract_archive(
+            &output::get_archive_path(config.target_path()), &build_path),
+    }
+
+    descriptor::write(config, &build_path);
+    println!("");
+
+    finish_build(config, &build_path, cache);
+    Ok(())
+}
+
+fn prepare_build(config: &Config, force: bool) -> Result<PathBuf, OutputError> {
+    println!("=== preparing output ===");
+    println!("Preparing mod folder at \"{}\"...", config.target_path().display());
+    output::check_target(config.target_path(), config.output_format, force)?;
+    descriptor::check_owned(config, force)?;
+    output::start_build(config.target_path(), force)
+}
+
+fn finish_build(config: &Config, build_path: &Path, cache: &mut BuildCache) {
+    println!("=== finishing output ===");
+    match config.output_format {
+        OutputFormat::Directory => {
+            output::reuse_unchanged(config.target_path(), build_path, cache);
+            output::finish_build(config.target_path(), build_path);
+        },
+        OutputFormat::Zip => output::finish_archive(config.target_path(), build_path),
+    }
+
+    // The launcher should only find the mod once it's fully in place
+    descriptor::write_outer(config);
+}
+
+/// Hashes what the country localisation depends on, the game's own file and the new entries.
+fn hash_localisation(config: &Config, data: &Eu4TargetData) -> u64 {
+    let entries: Vec<_> = data.localizations.iter().map(|l| (&l.key, &l.string)).collect();
+    cache::hash_value(&(
+        cache::GENERATOR_VERSION,
+        cache::hash_path(config.game_path().join(LOCALISATION_PATH)),
+        entries))
+}
+
+/// A parsed data file, along with the name of the file it's from.
+#[derive(Clone)]
+pub struct FileTable {
+    pub file_name: String,
+    pub data: Eu4Table
+}
+
+/// The game data the mod is generated from.
+pub struct Eu4SourceData {
+    pub provinces: Vec<FileTable>,
+    pub countries: Vec<FileTable>,
+    pub country_history: Vec<FileTable>,
+    pub diplomacy: Vec<FileTable>,
+    pub wars: Vec<FileTable>,
+    pub country_tags: Eu4Table,
+    pub areas: Eu4Table,
+    pub regions: Eu4Table,
+    pub superregions: Eu4Table,
+    pub map: Map,
+    pub cultures: Vec<FileTable>,
+}
+
+impl Eu4SourceData {
+    pub fn get_regions(&self) -> Regions {
+        Regions::new(&self.areas, &self.regions, &self.superregions)
+    }
+
+    pub fn get_cultures(&self) -> Cultures {
+        Cultures::new(&self.cultures)
+    }
+}
+
+pub fn load_eu4_data(config: &Config) -> Eu4SourceData {
+    println!("=== loading eu4 game data ===");
+
+    let provinces = load_eu4_data_from_folder(config.game_path(), "history", "provinces");
+    let countries = load_eu4_data_from_folder(config.game_path(), "common", "countries");
+    let country_history = load_eu4_data_from_folder(config.game_path(), "history", "countries");
+    let diplomacy = load_optional_eu4_data_from_folder(config.game_path(), "history", "diplomacy");
+    let wars = load_optional_eu4_data_from_folder(config.game_path(), "history", "wars");
+
+    println!("Loading country tags...");
+    let mut file = config.game_path().clone();
+    file.push("common"); file.push("country_tags"); file.push("00_countries.txt");
+    let text = file::read_all_win_1252(file);
+    let country_tags = Eu4Table::parse(&text);
+
+    println!("Loading map regions...");
+    let areas = load_optional_eu4_data(config.game_path(), "map", "area.txt");
+    let regions = load_optional_eu4_data(config.game_path(), "map", "region.txt");
+    let superregions = load_optional_eu4_data(config.game_path(), "map", "superregion.txt");
+
+    println!("Loading map...");
+    let map = Map::load(config.game_path());
+
+    let cultures = load_optional_eu4_data_from_folder(config.game_path(), "common", "cultures");
+
+    println!("");
+
+    Eu4SourceData {
+        provinces: provinces,
+        countries: countries,
+        country_history: country_history,
+        diplomacy: diplomacy,
+        wars: wars,
+        country_tags: country_tags,
+        areas: areas,
+        regions: regions,
+        superregions: superregions,
+        map: map,
+        cultures: cultures,
+    }
+}
+
+/// Loads a single data file, or an empty table if it doesn't exist.
+fn load_optional_eu4_data(base: &PathBuf, sub: &str, file_name: &str) -> Eu4Table {
+    let file = base.join(sub).join(file_name);
+    if file.is_file() {
+        Eu4Table::parse(&file::read_all_win_1252(file))
+    } else {
+        println!("No {}/{} found, skipping", sub, file_name);
+        Eu4Table::new()
+    }
+}
+
+fn load_optional_eu4_data_from_folder(
+    base: &PathBuf, sub1: &str, sub2: &str
+) -> Vec<FileTable> {
+    if base.join(sub1).join(sub2).is_dir() {
+        load_eu4_data_from_folder(base, sub1, sub2)
+    } else {
+        println!("No {}/{} found, skipping", sub1, sub2);
+        Vec::new()
+    }
+}
+
+pub fn load_eu4_data_from_folder(base: &PathBuf, sub1: &str, sub2: &str) -> Vec<FileTable> {
+    println!("Loading {}/{}...", sub1, sub2);
+
+    // Get a path for the folder the data is in
+    let mut dir = base.clone();
+    dir.push(sub1);
+    dir.push(sub2);
+    assert!(dir.is_dir(), "\"{}\" is not an existing directory", dir.display());
+
+    let start = Instant::now();
+
+    // Get all the data files from the directory, skipping anything else that may be in there
+    let files = file::sort_data_files(dir.read_dir().unwrap()
+        .map(|file_r| file_r.unwrap().path())
+        .filter(|path| path.is_file())
+        .collect());
+
+    // Load and parse the files in parallel, this keeps the order they were in
+    let data: Vec<_> = files.into_par_iter()
+        .map(|(file_name, path)| {
+            let text = file::read_all_win_1252(path);
+            FileTable {
+                file_name: file_name,
+                data: Eu4Table::parse(&text)
+            }
+        })
+        .collect();
+
+    let elapsed = start.elapsed();
+    println!("Loaded {} files in {}.{:03}s", data.len(), elapsed.as_secs(), elapsed.subsec_millis());
+
+    data
+}
+
+pub struct Eu4Localization {
+    pub key: String,
+    pub string: String,
+}
+
+/// A flag that should be generated for a country.
+pub struct Eu4FlagRequest {
+    pub tag: String,
+    pub color: Rgb,
+    pub color_alt: Rgb,
+    pub seed: Seed,
+}
+
+/// The data of the generated mod.
+pub struct Eu4TargetData {
+    pub provinces: Vec<FileTable>,
+    pub countries: Vec<FileTable>,
+    pub country_history: Vec<FileTable>,
+    pub country_tags: Eu4Table,
+    pub hre_history: Eu4Table,
+    /// The game's diplomacy files that had to be changed to use the new countries.
+    pub diplomacy: Vec<FileTable>,
+    /// The game's war files that had to be changed to use the new countries.
+    pub wars: Vec<FileTable>,
+    pub localizations: Vec<Eu4Localization>,
+    pub flag_requests: Vec<Eu4FlagRequest>,
+    /// Provinces that aren't split off from their owner, by index into `provinces`.
+    pub kept_provinces: BTreeSet<usize>,
+    /// Groups of provinces that become a single country together, by index into `provinces`.
+    /// The first province of a group is the country's capital.
+    pub clusters: Vec<Vec<usize>>,
+    /// The changed history of the game's own countries, like those that kept some of their
+    /// provinces.
+    pub kept_country_history: Vec<FileTable>,
+    /// Where each country came from, the same length and order as `countries` and
+    /// `country_history`.
+    pub origins: Vec<CountryOrigin>,
+}
+
+/// Where a generated country came from.
+pub struct CountryOrigin {
+    pub tag: String,
+    /// The tag of the country that owned the province in the game's data.
+    pub old_tag: String,
+    /// The index of the country's capital province in the target data's provinces.
+    pub province: usize,
+    pub province_id: String,
+    pub province_name: String,
+    /// The ids of all provinces the country starts with, the first is its capital.
+    pub province_ids: Vec<String>,
+    pub seed: Seed,
+}
+
+impl CountryOrigin {
+    pub fn owns(&self, province_id: &str) -> bool {
+        self.province_ids.iter().any(|id| id == province_id)
+    }
+}
+
+/// Runs the passes over the game data in order, turning it into the mod's data.
+pub fn process_eu4_data(
+    data: Eu4SourceData, passes: &[Box<dyn Pass>], tag_map: &mut TagMap, seed: Seed
+) -> Eu4TargetData {
+    println!("=== processing ===");
+    println!("Using seed {}", seed.0);
+
+    // Create copies of data for the passes to change
+    let mut target_data = Eu4TargetData {
+        provinces: data.provinces.clone(),
+        countries: Vec::new(),
+        country_history: Vec::new(),
+        country_tags: data.country_tags.clone(),
+        hre_history: Eu4Table::new(),
+        diplomacy: Vec::new(),
+        wars: Vec::new(),
+        localizations: Vec::new(),
+        flag_requests: Vec::new(),
+        kept_provinces: BTreeSet::new(),
+        clusters: Vec::new(),
+        kept_country_history: Vec::new(),
+        origins: Vec::new(),
+    };
+
+    let mut context = PassContext {
+        source: &data,
+        tag_map: tag_map,
+        seed: seed,
+    };
+    for pass in passes {
+        pass.run(&mut target_data, &mut context);
+    }
+
+    println!("");
+
+    target_data
+}
+
+/// Removes all dated history entries from the table.
+pub fn clear_events(table: &mut Eu4Table) {
+    table.values = table.values.iter()
+        .filter(|v| v.key.len() == 0 || !v.key.chars().nth(0).unwrap().is_digit(10))
+        .map(|v| v.clone())
+        .collect();
+}
+
+fn get_next_valid_tag(tag_num: &mut i32, country_tags: &Eu4Table, tag_map: &TagMap) -> String {
+    loop {
+        // Get the next tag and increment
+        let tag = get_tag_for_num(*tag_num);
+        *tag_num += 1;
+
+        // Make sure it's not one of these special cases
+        if tag == "AUX" || tag == "CON" || tag == "AND" {
+            continue;
+        }
+
+        // Make sure it's not already in use
+        if country_tags.values.iter().any(|v| v.key == tag) {
+            continue;
+        }
+
+        // Make sure it's not reserved for another province
+        if tag_map.contains_tag(&tag) {
+            continue;
+        }
+
+        // It's valid, return it
+        return tag;
+    }
+}
+
+fn get_tag_for_num(num: i32) -> String {
+    let mut b = [b'A'; 3];
+
+    b[0] += (num / (26*26)) as u8;
+    b[1] += ((num % (26*26)) / 26) as u8;
+    b[2] += (num % 26) as u8;
+
+    ::std::str::from_utf8(&b).unwrap().to_string()
+}
+
+pub fn write_eu4_data(config: &Config, data: &Eu4TargetData) {
+    println!("=== serializing to target ===");
+
+    write_eu4_data_to_folder(config.target_path(), "history", "provinces", &data.provinces);
+    write_eu4_data_to_folder(config.target_path(), "common", "countries", &data.countries);
+    write_eu4_data_to_folder(config.target_path(), "history", "countries", &data.country_history);
+    if data.kept_country_history.len() != 0 {
+        write_eu4_data_to_folder(
+            config.target_path(), "history", "countries", &data.kept_country_history);
+    }
+
+    // Create the country tags file
+    println!("Serializing country tags...");
+    let mut file = config.target_path().clone();
+    file.push("common"); file.push("country_tags");
+    fs::create_dir_all(&file).unwrap();
+    file.push("00_countries.txt");
+    file::write_all_win_1252(file, &data.country_tags.serialize());
+
+    if data.diplomacy.len() != 0 {
+        write_eu4_data_to_folder(config.target_path(), "history", "diplomacy", &data.diplomacy);
+    }
+    if data.wars.len() != 0 {
+        write_eu4_data_to_folder(config.target_path(), "history", "wars", &data.wars);
+    }
+
+    // Create the HRE file, if the emperor wasn't shattered the game's own file still applies
+    if data.hre_history.values.len() != 0 {
+        println!("Serializing HRE history...");
+        let mut file = config.target_path().clone();
+        file.push("history"); file.push("diplomacy");
+        fs::create_dir_all(&file).unwrap();
+        file.push("hre.txt");
+        file::write_all_win_1252(file, &data.hre_history.serialize());
+    }
+
+    println!("");
+}
+
+pub fn write_eu4_data_to_folder(base: &PathBuf, sub1: &str, sub2: &str, entries: &Vec<FileTable>) {
+    println!("Serializing {}/{}...", sub1, sub2);
+
+    // Create the directory
+    let mut dir = base.clone();
+    dir.push(sub1);
+    dir.push(sub2);
+    fs::create_dir_all(&dir).unwrap();
+
+    // Write all the data to it
+    for entry in entries {
+        let mut file = dir.clone();
+        file.push(&entry.file_name);
+
+        file::write_all_win_1252(file, &entry.data.serialize());
+    }
+}
+
+pub fn write_eu4_localisation(config: &Config, data: &Eu4TargetData) {
+    println!("Generating country localisation...");
+
+    // Read in the original
+    let mut text = file::read_all_text(config.game_path().join(LOCALISATION_PATH));
+
+    // Append our own localization data
+    for entry in &data.localizations {
+        text.push_str(&format!("\n {}: \"{}\"", entry.key, entry.string));
+        text.push_str(&format!("\n {}_ADJ: \"{}\"", entry.key, entry.string));
+    }
+
+    // Write the result
+    let target_loc = config.target_path().join(LOCALISATION_PATH);
+    fs::create_dir_all(target_loc.parent().unwrap()).unwrap();
+    file::write_all_text(&target_loc, &text);
+}
@@ -1,368 +1,214 @@
-extern crate encoding;
-extern crate imagefmt;
-extern crate palette;
-extern crate rand;
-extern crate toml;
-extern crate eu4data;
+extern crate clap;
+extern crate shattered_generator;
 
-mod config;
-mod file;
-mod flags;
+mod cli;
 
 use std::fs;
-use std::path::PathBuf;
-use palette::Rgb;
-use palette::pixel::Srgb;
-use rand::{Rng, StdRng};
-use config::Config;
-use eu4data::{Eu4Table, Eu4Value};
+use shattered_generator::{descriptor, file, flags, output, passes};
+use shattered_generator::{load_and_process, rewrite_mod, write_mod, StageHashes};
+use shattered_generator::{load_eu4_data, process_eu4_data};
+use shattered_generator::cache::BuildCache;
+use shattered_generator::config::Config;
+use shattered_generator::output::OutputFormat;
+use shattered_generator::seed::Seed;
+use shattered_generator::tags::TagMap;
+use cli::{Command, Options};
 
 fn main() {
-    let config = Config::load();
-    prepare_output(&config);
-    let source_data = load_eu4_data(&config);
-    let target_data = process_eu4_data(source_data);
-    write_eu4_data(&config, &target_data);
-
-    println!("=== generating polish data ===");
-    write_eu4_localisation(&config, &target_data);
-    flags::generate(&config, &target_data);
-}
-
-fn prepare_output(config: &Config) {
-    println!("=== preparing output ===");
-    println!("Preparing mod folder at \"{}\"...", config.target_path.display());
-
-    // Delete the old mod folder if it's already there
-    if config.target_path.is_dir() {
-        println!("Target already exists, deleting stale...");
-        fs::remove_dir_all(&config.target_path).unwrap();
+    let options = Options::parse();
+    let mut config = Config::load(&options.config_path).unwrap_or_else(|e| exit_with_error(e));
+    options.apply_to(&mut config);
+    config.validate().unwrap_or_else(|e| exit_with_error(e));
+
+    match options.command {
+        Command::Generate => generate(&config, &options),
+        Command::Validate => validate(&config),
+        Command::Diff => diff(&config),
+        Command::Flags => regenerate_flags(&config, &options),
+        Command::Stats => stats(&config),
     }
-
-    // Create a new mod folder for us
-    fs::create_dir_all(&config.target_path).unwrap();
-
-    // Copy over the .mod file TODO: Auto-generate
-    let mut shattered_mod = config.target_path.clone();
-    shattered_mod.push("..");
-    shattered_mod.push("shattered.mod");
-    fs::copy("./assets/shattered.mod", shattered_mod).unwrap();
-
-    println!("");
-}
-
-#[derive(Clone)]
-struct FileTable {
-    file_name: String,
-    data: Eu4Table
 }
 
-struct Eu4SourceData {
-    provinces: Vec<FileTable>,
-    countries: Vec<FileTable>,
-    country_history: Vec<FileTable>,
-    country_tags: Eu4Table,
+fn exit_with_error<E: ::std::fmt::Display>(error: E) -> ! {
+    println!("{}", error);
+    ::std::process::exit(1);
 }
 
-fn load_eu4_data(config: &Config) -> Eu4SourceData {
-    println!("=== loading eu4 game data ===");
-
-    let provinces = load_eu4_data_from_folder(&config.game_path, "history", "provinces");
-    let countries = load_eu4_data_from_folder(&config.game_path, "common", "countries");
-    let country_history = load_eu4_data_from_folder(&config.game_path, "history", "countries");
-
-    println!("Loading country tags...");
-    let mut file = config.game_path.clone();
-    file.push("common"); file.push("country_tags"); file.push("00_countries.txt");
-    let text = file::read_all_win_1252(file);
-    let country_tags = Eu4Table::parse(&text);
-
-    println!("");
+fn generate(config: &Config, options: &Options) {
+    // Check this before doing any work, so we don't find out after processing everything
+    output::check_target(config.target_path(), config.output_format, options.force)
+        .unwrap_or_else(|e| exit_with_error(e));
+    descriptor::check_owned(config, options.force).unwrap_or_else(|e| exit_with_error(e));
 
-    Eu4SourceData {
-        provinces: provinces,
-        countries: countries,
-        country_history: country_history,
-        country_tags: country_tags,
+    let mut tag_map = TagMap::load(&config.tag_map_path);
+    let mut cache = load_cache(config, options);
+    let seed = get_seed(config);
+    if config.seed.is_none() {
+        println!("No seed set, the previous output can't be re-used");
     }
-}
-
-fn load_eu4_data_from_folder(base: &PathBuf, sub1: &str, sub2: &str) -> Vec<FileTable> {
-    println!("Loading {}/{}...", sub1, sub2);
 
-    // Get a path for the folder the data is in
-    let mut dir = base.clone();
-    dir.push(sub1);
-    dir.push(sub2);
-    assert!(dir.is_dir(), "\"{}\" is not an existing directory", dir.display());
-
-    let mut data = Vec::new();
+    // If nothing processing depends on changed since the last run, the existing output still
+    // has its results
+    let hashes = StageHashes::new(config, &tag_map, seed);
+    if cache.is_unchanged("process", hashes.process) &&
+        output::target_exists(config.target_path(), config.output_format) {
+        if cache.is_unchanged("output", hashes.output) {
+            println!("Nothing changed since the last run, skipping generation");
+            return;
+        }
 
-    // Get all the files from the directory
-    for file_r in dir.read_dir().unwrap() {
-        let file = file_r.unwrap();
-        //println!("Loading {:?}...", file.file_name());
+        println!("Only the output settings changed since the last run, re-using its output");
+        if options.dry_run {
+            println!("Dry run, not writing mod to \"{}\"", config.target_path().display());
+            return;
+        }
+        rewrite_mod(config, options.force, &mut cache).unwrap_or_else(|e| exit_with_error(e));
+        cache.set("output", hashes.output);
+        cache.save();
+        return;
+    }
+    if cache.is_unchanged("load", hashes.load) {
+        println!("The game files didn't change since the last run, but the config or tags did");
+    }
 
-        // Load the file
-        let text = file::read_all_win_1252(file.path());
-        let file_data = Eu4Table::parse(&text);
+    let target_data = load_and_process(config, &mut tag_map, seed)
+        .unwrap_or_else(|e| exit_with_error(e));
 
-        data.push(FileTable {
-            file_name: file.file_name().to_str().unwrap().into(),
-            data: file_data
-        });
+    if options.dry_run {
+        println!("Dry run, not writing mod to \"{}\"", config.target_path().display());
+        return;
     }
 
-    data
-}
+    tag_map.save(&config.tag_map_path);
+    write_mod(config, &target_data, options.force, &mut cache)
+        .unwrap_or_else(|e| exit_with_error(e));
 
-struct Eu4Localization {
-    key: String,
-    string: String,
+    // Remember what this output was generated from, including the tags we just assigned
+    let hashes = hashes.with_tag_map(config, &tag_map, seed);
+    cache.set("load", hashes.load);
+    cache.set("process", hashes.process);
+    cache.set("output", hashes.output);
+    cache.save();
 }
 
-struct Eu4FlagRequest {
-    tag: String,
-    color: Rgb,
-    color_alt: Rgb
-}
+fn validate(config: &Config) {
+    // Processing the data will run into any problems with it, we just don't write the results
+    let mut tag_map = TagMap::load(&config.tag_map_path);
+    let target_data = load_and_process(config, &mut tag_map, get_seed(config))
+        .unwrap_or_else(|e| exit_with_error(e));
 
-pub struct Eu4TargetData {
-    provinces: Vec<FileTable>,
-    countries: Vec<FileTable>,
-    country_history: Vec<FileTable>,
-    country_tags: Eu4Table,
-    hre_history: Eu4Table,
-    localizations: Vec<Eu4Localization>,
-    flag_requests: Vec<Eu4FlagRequest>,
+    println!("=== validation successful ===");
+    println!("{} countries can be generated", target_data.countries.len());
 }
 
-fn process_eu4_data(data: Eu4SourceData) -> Eu4TargetData {
-    println!("=== processing ===");
-
-    // Create copies of data for us to use
-    let mut provinces = data.provinces.clone();
-    let mut countries: Vec<FileTable> = Vec::new();
-    let mut country_history: Vec<FileTable> = Vec::new();
-    let mut country_tags = data.country_tags.clone();
-    let mut hre_history = Eu4Table::new();
-    let mut localizations: Vec<Eu4Localization> = Vec::new();
-    let mut flag_requests: Vec<Eu4FlagRequest> = Vec::new();
-
-    println!("Clearing events on provinces...");
-    for province in &mut provinces {
-        clear_events(&mut province.data);
+fn diff(config: &Config) {
+    if config.seed.is_none() {
+        println!("No seed set, colors and flags will always show up as changed");
     }
 
-    println!("Generating new countries...");
-    let mut rand = StdRng::new().unwrap();
-    let mut tag_num = 0;
-    for province in &mut provinces {
-        // Check the province's owner tag, if it has one
-        let old_country_tag = {
-            if let Some(tag) = province.data.get("owner") {
-                tag.as_str().to_string()
-            } else {
-                continue; // No owner, we can skip this province
-            }
-        };
-
-        // Find the country data for this province
-        let old_country_file = country_tags.get(&old_country_tag).unwrap().as_str().to_string();
-        let old_country = data.countries.iter()
-            .find(|d| (String::from("countries/") + &d.file_name) == old_country_file).unwrap();
-        let old_country_history = data.country_history.iter()
-            .find(|f| f.file_name.starts_with(&old_country_tag)).unwrap();
-
-        // Find out the name of this province from the file
-        // TODO: Retrieve this from the localization file instead of from the file name
-        let province_segments: Vec<_> = province.file_name
-            .split(|c| c == ' ' || c == '-' || c == '.')
-            .filter(|s| s.len() != 0)
-            .collect();
-        let province_name = province_segments.iter().nth(1).unwrap().to_string();
-        let province_id = province_segments.iter().nth(0).unwrap().to_string();
-        let new_country_file_name = format!("{}.txt", province_name);
-
-        // Make a new country with the old country's and data
-        let mut new_country = old_country.clone();
-        new_country.file_name = new_country_file_name.clone();
-        let mut new_country_history = old_country_history.clone();
-
-        // Clear the events on the new country
-        clear_events(&mut new_country.data);
-        clear_events(&mut new_country_history.data);
-
-        // Generate a new tag for the country and add it to the tags list
-        let new_country_tag = get_next_valid_tag(&mut tag_num, &country_tags);
-        country_tags.set(
-            &new_country_tag,
-            Eu4Value::String(String::from("countries/") + &new_country_file_name));
-        new_country_history.file_name = format!("{} - {}", new_country_tag, new_country_file_name);
-        localizations.push(Eu4Localization { key: new_country_tag.clone(), string: province_name.clone() });
-
-        // Make the country's culture and religion match the province it was generated from
-        new_country_history.data.set("primary_culture", province.data.get("culture").unwrap().clone());
-        new_country_history.data.set("religion", province.data.get("religion").unwrap().clone());
-
-        // Replace papalcies with theocratic governments
-        if new_country_history.data.get("government").unwrap().as_str() == "papal_government" {
-            new_country_history.data.set("government", Eu4Value::String("theocratic_government".into()));
-        }
-
-        // Generate a color for the country
-        // TODO: Improve color generation
-        let color: [u8; 3] = [rand.gen(), rand.gen(), rand.gen()];
-        let color_alt: [u8; 3] = [rand.gen(), rand.gen(), rand.gen()];
-        new_country.data.set("color", Eu4Value::color(color[0], color[1], color[2]));
-        flag_requests.push(Eu4FlagRequest {
-            tag: new_country_tag.clone(),
-            color: Rgb::from(Srgb::from_pixel(&color)),
-            color_alt: Rgb::from(Srgb::from_pixel(&color_alt))
-        });
-
-        // Update the province to be owned by the new country
-        province.data.set("owner", Eu4Value::String(new_country_tag.clone()));
-        province.data.set("controller", Eu4Value::String(new_country_tag.clone()));
-        province.data.set("add_core", Eu4Value::String(new_country_tag.clone()));
-
-        // Fix the HRE electors, only stay an elector if the country was the old country's capital
-        if new_country_history.data.get("elector").map(|v| v.as_str() == "yes").unwrap_or(false) {
-            if old_country_history.data.get("capital").unwrap().as_str() != province_id {
-                new_country_history.data.set("elector", Eu4Value::String("no".into()));
-            } else {
-                println!("Granted elector status to {}", province_name);
-            }
-        }
-
-        // If the old country was HAB (Austria), elect the capital as emperor
-        if old_country_tag == "HAB" {
-            if old_country_history.data.get("capital").unwrap().as_str() == province_id {
-                hre_history.set("1437.12.9", Eu4Value::String(new_country_tag));
-                hre_history.set("1806.7.12", Eu4Value::String("xxx".into()));
-                println!("Granted emperor status to {}", province_name);
-            }
+    // Generate the mod in a temporary location so we can compare it to the existing mod
+    let mut diff_config = config.clone();
+    // Every run gets its own folder, so runs at the same time don't remove each other's files
+    let mut diff_path = ::std::env::temp_dir();
+    diff_path.push(format!("shattered-generator-diff-{}", ::std::process::id()));
+    diff_path.push("shattered");
+    diff_config.target_path = Some(diff_path);
+    diff_config.output_format = OutputFormat::Directory;
+
+    // If the existing mod is an archive we extract it so we can compare the files in it
+    let mut old_path = config.target_path().clone();
+    if config.output_format == OutputFormat::Zip {
+        old_path = diff_config.target_path().with_file_name("old");
+        let archive_path = output::get_archive_path(config.target_path());
+        if archive_path.is_file() {
+            output::extract_archive(&archive_path, &old_path);
         }
-
-        // Store the actual data in the lists
-        countries.push(new_country);
-        country_history.push(new_country_history);
-    }
-
-    println!("");
-
-    Eu4TargetData {
-        provinces: provinces,
-        countries: countries,
-        country_history: country_history,
-        country_tags: country_tags,
-        hre_history: hre_history,
-        localizations: localizations,
-        flag_requests: flag_requests,
     }
-}
-
-fn clear_events(table: &mut Eu4Table) {
-    table.values = table.values.iter()
-        .filter(|v| v.key.len() == 0 || !v.key.chars().nth(0).unwrap().is_digit(10))
-        .map(|v| v.clone())
-        .collect();
-}
 
-fn get_next_valid_tag(tag_num: &mut i32, country_tags: &Eu4Table) -> String {
-    loop {
-        // Get the next tag and increment
-        let tag = get_tag_for_num(*tag_num);
-        *tag_num += 1;
-
-        // Make sure it's not one of these special cases
-        if tag == "AUX" || tag == "CON" || tag == "AND" {
-            continue;
+    let mut tag_map = TagMap::load(&config.tag_map_path);
+    let target_data = load_and_process(config, &mut tag_map, get_seed(config))
+        .unwrap_or_else(|e| exit_with_error(e));
+    write_mod(&diff_config, &target_data, false, &mut BuildCache::disabled())
+        .unwrap_or_else(|e| exit_with_error(e));
+
+    println!("=== comparing to existing mod ===");
+    let old_files = file::list_files(&old_path);
+    let new_files = file::list_files(diff_config.target_path());
+    let mut changes = 0;
+
+    for new_file in &new_files {
+        if !old_files.contains(new_file) {
+            println!("+ {}", new_file.display());
+            changes += 1;
+        } else if !file::same_contents(
+            old_path.join(new_file), diff_config.target_path().join(new_file)) {
+            println!("~ {}", new_file.display());
+            changes += 1;
         }
-
-        // Make sure it's not already in use
-        if country_tags.values.iter().any(|v| v.key == tag) {
-            continue;
+    }
+    for old_file in &old_files {
+        if !new_files.contains(old_file) {
+            println!("- {}", old_file.display());
+            changes += 1;
         }
-
-        // It's valid, return it
-        return tag;
     }
-}
-
-fn get_tag_for_num(num: i32) -> String {
-    let mut b = [b'A'; 3];
 
-    b[0] += (num / (26*26)) as u8;
-    b[1] += ((num % (26*26)) / 26) as u8;
-    b[2] += (num % 26) as u8;
-
-    ::std::str::from_utf8(&b).unwrap().to_string()
+    println!("{} files changed", changes);
+    fs::remove_dir_all(diff_config.target_path().parent().unwrap()).unwrap();
 }
 
-fn write_eu4_data(config: &Config, data: &Eu4TargetData) {
-    println!("=== serializing to target ===");
-
-    write_eu4_data_to_folder(&config.target_path, "history", "provinces", &data.provinces);
-    write_eu4_data_to_folder(&config.target_path, "common", "countries", &data.countries);
-    write_eu4_data_to_folder(&config.target_path, "history", "countries", &data.country_history);
-
-    // Create the country tags file
-    println!("Serializing country tags...");
-    let mut file = config.target_path.clone();
-    file.push("common"); file.push("country_tags");
-    fs::create_dir_all(&file).unwrap();
-    file.push("00_countries.txt");
-    file::write_all_win_1252(file, &data.country_tags.serialize());
-
-    // Create the HRE file
-    println!("Serializing HRE history...");
-    let mut file = config.target_path.clone();
-    file.push("history"); file.push("diplomacy");
-    fs::create_dir_all(&file).unwrap();
-    file.push("hre.txt");
-    file::write_all_win_1252(file, &data.hre_history.serialize());
-
-    println!("");
-}
-
-fn write_eu4_data_to_folder(base: &PathBuf, sub1: &str, sub2: &str, entries: &Vec<FileTable>) {
-    println!("Serializing {}/{}...", sub1, sub2);
-
-    // Create the directory
-    let mut dir = base.clone();
-    dir.push(sub1);
-    dir.push(sub2);
-    fs::create_dir_all(&dir).unwrap();
-
-    // Write all the data to it
-    for entry in entries {
-        let mut file = dir.clone();
-        file.push(&entry.file_name);
-
-        file::write_all_win_1252(file, &entry.data.serialize());
+fn regenerate_flags(config: &Config, options: &Options) {
+    if config.output_format != OutputFormat::Directory {
+        exit_with_error("Flags can only be regenerated for mods written as a directory, \
+                         use generate to regenerate the whole archive");
     }
-}
-
-fn write_eu4_localisation(config: &Config, data: &Eu4TargetData) {
-    println!("Generating country localisation...");
+    output::check_owned(config.target_path(), options.force)
+        .unwrap_or_else(|e| exit_with_error(e));
 
-    // Read in the original
-    let mut game_loc = config.game_path.clone();
-    game_loc.push("localisation");
-    game_loc.push("countries_l_english.yml");
-    let mut text = file::read_all_text(&game_loc);
+    let mut tag_map = TagMap::load(&config.tag_map_path);
+    let mut cache = load_cache(config, options);
+    let target_data = load_and_process(config, &mut tag_map, get_seed(config))
+        .unwrap_or_else(|e| exit_with_error(e));
 
-    // Append our own localization data
-    for entry in &data.localizations {
-        text.push_str(&format!("\n {}: \"{}\"", entry.key, entry.string));
-        text.push_str(&format!("\n {}_ADJ: \"{}\"", entry.key, entry.string));
+    if options.dry_run {
+        println!("Dry run, not writing flags to \"{}\"", config.target_path().display());
+        return;
     }
 
-    // Write the result
-    let mut target_loc = config.target_path.clone();
-    target_loc.push("localisation");
-    fs::create_dir_all(&target_loc).unwrap();
-    target_loc.push("countries_l_english.yml");
-    file::write_all_text(&target_loc, &text);
+    println!("=== generating flags ===");
+    flags::generate(config, &target_data, Some(config.target_path()), &mut cache);
+    cache.save();
+}
+
+fn stats(config: &Config) {
+    let passes = passes::get_passes(config).unwrap_or_else(|e| exit_with_error(e));
+    let mut tag_map = TagMap::load(&config.tag_map_path);
+    let source_data = load_eu4_data(config);
+    let province_count = source_data.provinces.len();
+    let country_count = source_data.countries.len();
+    let bordering_count = source_data.map.provinces().len();
+    let target_data = process_eu4_data(source_data, &passes, &mut tag_map, get_seed(config));
+
+    println!("=== statistics ===");
+    println!("Provinces: {}", province_count);
+    println!("Provinces with borders: {}", bordering_count);
+    println!("Original countries: {}", country_count);
+    println!("Generated countries: {}", target_data.countries.len());
+    println!("Localisation entries: {}", target_data.localizations.len());
+    println!("Flags: {}", target_data.flag_requests.len());
+    println!("Electors: {}", target_data.country_history.iter()
+        .filter(|c| c.data.get("elector").map(|v| v.as_str() == "yes").unwrap_or(false))
+        .count());
+}
+
+fn get_seed(config: &Config) -> Seed {
+    config.seed.map(Seed).unwrap_or_else(Seed::random)
+}
+
+fn load_cache(config: &Config, options: &Options) -> BuildCache {
+    if options.no_cache {
+        BuildCache::disabled()
+    } else {
+        BuildCache::load(&config.cache_path)
+    }
 }
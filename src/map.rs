@@ -0,0 +1,259 @@
+//! The province map, used to find out which provinces border each other.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::path::Path;
+use std::time::Instant;
+use imagefmt::{self, ColFmt};
+use file;
+
+pub struct Map {
+    /// The provinces bordering every province, by province id.
+    neighbours: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl Map {
+    /// Creates a map without any borders.
+    pub fn new() -> Self {
+        Map {
+            neighbours: BTreeMap::new(),
+        }
+    }
+
+    /// Loads the borders between provinces from the game's map folder, provinces border each
+    /// other if their pixels in provinces.bmp touch or if adjacencies.csv connects them.
+    pub fn load(game_path: &Path) -> Self {
+        let mut map = Map::new();
+
+        let map_path = game_path.join("map");
+        let definition_path = map_path.join("definition.csv");
+        let provinces_path = map_path.join("provinces.bmp");
+        if !definition_path.is_file() || !provinces_path.is_file() {
+            println!("No map/definition.csv or map/provinces.bmp found, skipping borders");
+            return map;
+        }
+
+        let start = Instant::now();
+
+        let colors = parse_definitions(&file::read_all_win_1252(&definition_path));
+        let image = imagefmt::read(&provinces_path, ColFmt::RGB)
+            .unwrap_or_else(|e| panic!("Could not read \"{}\": {:?}", provinces_path.display(), e));
+        map.add_pixel_borders(image.w, image.h, &image.buf, &colors);
+
+        let adjacencies_path = map_path.join("adjacencies.csv");
+        if adjacencies_path.is_file() {
+            for (from, to) in parse_adjacencies(&file::read_all_win_1252(&adjacencies_path)) {
+                map.add_border(&from, &to);
+            }
+        }
+
+        let elapsed = start.elapsed();
+        println!("Found borders of {} provinces in {}.{:03}s",
+            map.neighbours.len(), elapsed.as_secs(), elapsed.subsec_millis());
+
+        map
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.neighbours.is_empty()
+    }
+
+    /// Gets all provinces that border at least one other province, sorted by id.
+    pub fn provinces(&self) -> Vec<&str> {
+        self.neighbours.keys().map(|id| id.as_str()).collect()
+    }
+
+    /// Gets the provinces bordering the province, sorted by id.
+    pub fn neighbours(&self, province_id: &str) -> Vec<&str> {
+        self.neighbours.get(province_id)
+            .map(|n| n.iter().map(|id| id.as_str()).collect())
+            .unwrap_or_else(Vec::new)
+    }
+
+    /// Goes through the provinces reachable from the start province breadth first, only
+    /// passing through provinces the filter accepts. The start province always comes first.
+    pub fn bfs<'a, F>(&'a self, start: &str, filter: F) -> Bfs<'a, F>
+        where F: FnMut(&str) -> bool
+    {
+        let mut queue = VecDeque::new();
+        queue.push_back(start.to_string());
+        let mut visited = HashSet::new();
+        visited.insert(start.to_string());
+
+        Bfs {
+            map: self,
+            filter: filter,
+            queue: queue,
+            visited: visited,
+        }
+    }
+
+    /// Splits the provinces the filter accepts into groups that are connected through each other.
+    pub fn connected_components<F>(&self, mut filter: F) -> Vec<Vec<String>>
+        where F: FnMut(&str) -> bool
+    {
+        let mut components: Vec<Vec<String>> = Vec::new();
+        let mut found = HashSet::new();
+
+        for province in self.provinces() {
+            if found.contains(province) || !filter(province) {
+                continue;
+            }
+
+            let component: Vec<String> = self.bfs(province, |id| filter(id)).collect();
+            found.extend(component.iter().cloned());
+            components.push(component);
+        }
+
+        components
+    }
+
+    pub fn add_border(&mut self, a: &str, b: &str) {
+        if a == b {
+            return;
+        }
+        self.neighbours.entry(a.to_string()).or_insert_with(BTreeSet::new).insert(b.to_string());
+        self.neighbours.entry(b.to_string()).or_insert_with(BTreeSet::new).insert(a.to_string());
+    }
+
+    /// Adds borders between all provinces that have touching pixels in an RGB image, the map
+    /// wraps around horizontally.
+    fn add_pixel_borders(
+        &mut self, width: usize, height: usize, pixels: &[u8], colors: &HashMap<[u8; 3], String>
+    ) {
+        let pixel = |x: usize, y: usize| {
+            let i = (y * width + x) * 3;
+            [pixels[i], pixels[i + 1], pixels[i + 2]]
+        };
+
+        // Collect the distinct pairs of colors first, most neighbouring pixels are the same
+        let mut pairs = BTreeSet::new();
+        for y in 0..height {
+            for x in 0..width {
+                let color = pixel(x, y);
+                let right = pixel((x + 1) % width, y);
+                if color != right {
+                    pairs.insert((color, right));
+                }
+                if y + 1 < height {
+                    let below = pixel(x, y + 1);
+                    if color != below {
+                        pairs.insert((color, below));
+                    }
+                }
+            }
+        }
+
+        for (a, b) in pairs {
+            if let (Some(a), Some(b)) = (colors.get(&a), colors.get(&b)) {
+                self.add_border(a, b);
+            }
+        }
+    }
+}
+
+/// A breadth first search through the map, created by `Map::bfs`.
+pub struct Bfs<'a, F> {
+    map: &'a Map,
+    filter: F,
+    queue: VecDeque<String>,
+    visited: HashSet<String>,
+}
+
+impl<'a, F: FnMut(&str) -> bool> Iterator for Bfs<'a, F> {
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        let current = self.queue.pop_front()?;
+
+        for neighbour in self.map.neighbours(&current) {
+            if !self.visited.contains(neighbour) && (self.filter)(neighbour) {
+                self.visited.insert(neighbour.to_string());
+                self.queue.push_back(neighbour.to_string());
+            }
+        }
+
+        Some(current)
+    }
+}
+
+/// Parses definition.csv into the province id of every color.
+fn parse_definitions(text: &str) -> HashMap<[u8; 3], String> {
+    let mut colors = HashMap::new();
+
+    // The first line is a header, every line after is "province;red;green;blue;name;x"
+    for line in text.lines().skip(1) {
+        let fields: Vec<_> = line.split(';').collect();
+        if fields.len() < 4 {
+            continue;
+        }
+
+        let channels: Vec<_> = fields[1..4].iter().filter_map(|c| c.trim().parse().ok()).collect();
+        if channels.len() == 3 {
+            colors.insert([channels[0], channels[1], channels[2]], fields[0].trim().to_string());
+        }
+    }
+
+    colors
+}
+
+/// Parses adjacencies.csv into pairs of connected provinces, like straits.
+fn parse_adjacencies(text: &str) -> Vec<(String, String)> {
+    // The first line is a header, every line after is "from;to;type;...", the list ends with -1
+    text.lines().skip(1)
+        .map(|line| line.split(';').map(|f| f.trim()).collect::<Vec<_>>())
+        .take_while(|fields| fields[0] != "-1")
+        .filter(|fields| fields.len() >= 2 && fields[0].len() != 0 && fields[1].len() != 0)
+        .filter(|fields| fields.get(2) != Some(&"impassable"))
+        .map(|fields| (fields[0].to_string(), fields[1].to_string()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Map, parse_adjacencies, parse_definitions};
+
+    #[test]
+    fn pixel_borders() {
+        let colors = parse_definitions(
+            "province;red;green;blue;x;x\n1;255;0;0;Stockholm;x\n2;0;255;0;Uppsala;x\n\
+             3;0;0;255;Kalmar;x\n");
+        let r = [255, 0, 0];
+        let g = [0, 255, 0];
+        let b = [0, 0, 255];
+        // 1 1 2
+        // 3 3 3
+        let pixels: Vec<u8> = [r, r, g, b, b, b].iter().flat_map(|p| p.iter().cloned()).collect();
+
+        let mut map = Map::new();
+        map.add_pixel_borders(3, 2, &pixels, &colors);
+
+        assert_eq!(map.neighbours("1"), vec!["2", "3"]);
+        assert_eq!(map.neighbours("2"), vec!["1", "3"]);
+    }
+
+    #[test]
+    fn adjacencies() {
+        let adjacencies = parse_adjacencies(
+            "From;To;Type;Through;start_x;start_y;stop_x;stop_y;adjacency_rule_name;Comment\n\
+             1;12;sea;1258;-1;-1;-1;-1;;Stockholm-Skane\n\
+             -1;-1;;-1;-1;-1;-1;-1;-1;\n");
+        assert_eq!(adjacencies, vec![("1".to_string(), "12".to_string())]);
+    }
+
+    #[test]
+    fn graph() {
+        // 1 - 2 - 3   4 - 5
+        let mut map = Map::new();
+        map.add_border("1", "2");
+        map.add_border("2", "3");
+        map.add_border("4", "5");
+
+        assert_eq!(map.neighbours("2"), vec!["1", "3"]);
+        assert_eq!(map.neighbours("6"), Vec::<&str>::new());
+        assert_eq!(map.bfs("1", |_| true).collect::<Vec<_>>(), vec!["1", "2", "3"]);
+        assert_eq!(map.bfs("1", |id| id != "2").collect::<Vec<_>>(), vec!["1"]);
+
+        let components = map.connected_components(|id| id != "2");
+        assert_eq!(components, vec![vec!["1"], vec!["3"], vec!["4", "5"]]);
+    }
+}
@@ -0,0 +1,224 @@
+//! Safe handling of the mod folder we write to. The generator only ever deletes folders that it
+//! marked as its own, and builds the mod next to the target so a failed run never leaves a
+//! half-written mod behind.
+
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+use zip::write::FileOptions;
+use cache::{self, BuildCache};
+use file;
+
+/// The file marking a folder as generated by us, and safe to delete.
+pub const MARKER_FILE: &'static str = ".shattered-generator";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum OutputFormat {
+    /// The mod is written as a folder of files.
+    Directory,
+    /// The mod is written as a single zip archive next to where the folder would be.
+    Zip,
+}
+
+#[derive(Debug)]
+pub enum OutputError {
+    /// The folder exists but wasn't generated by us, so we won't touch it.
+    NotOwned(PathBuf),
+}
+
+impl fmt::Display for OutputError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &OutputError::NotOwned(ref path) => write!(f,
+                "\"{}\" already exists but was not generated by Shattered Generator, refusing to \
+                 overwrite it. Check if target_path is correct, or use --force to overwrite it \
+                 anyway", path.display()),
+        }
+    }
+}
+
+/// Checks if we're allowed to overwrite the output for the target, in whichever format it's in.
+pub fn check_target(target_path: &Path, format: OutputFormat, force: bool)
+    -> Result<(), OutputError> {
+    match format {
+        OutputFormat::Directory => check_owned(target_path, force),
+        OutputFormat::Zip => check_archive_owned(&get_archive_path(target_path), force),
+    }
+}
+
+/// Checks if there's a previously generated mod for the target.
+pub fn target_exists(target_path: &Path, format: OutputFormat) -> bool {
+    match format {
+        OutputFormat::Directory => target_path.join(MARKER_FILE).is_file(),
+        OutputFormat::Zip => get_archive_path(target_path).is_file(),
+    }
+}
+
+/// Checks if we're allowed to overwrite the folder, which is the case if it's ours, empty or
+/// doesn't exist at all.
+pub fn check_owned(path: &Path, force: bool) -> Result<(), OutputError> {
+    if force || !path.exists() || path.join(MARKER_FILE).is_file() || is_empty_dir(path) {
+        Ok(())
+    } else {
+        Err(OutputError::NotOwned(path.into()))
+    }
+}
+
+/// Checks if we're allowed to overwrite the archive, which is the case if it contains our marker
+/// or doesn't exist at all.
+pub fn check_archive_owned(path: &Path, force: bool) -> Result<(), OutputError> {
+    if force || !path.exists() {
+        return Ok(());
+    }
+
+    let owned = File::open(path).ok()
+        .and_then(|f| ZipArchive::new(f).ok())
+        .map(|mut archive| archive.by_name(MARKER_FILE).is_ok())
+        .unwrap_or(false);
+    if owned {
+        Ok(())
+    } else {
+        Err(OutputError::NotOwned(path.into()))
+    }
+}
+
+/// Creates a fresh folder next to the target to build the mod in. The target itself should have
+/// been checked with `check_target` before this.
+pub fn start_build(target_path: &Path, force: bool) -> Result<PathBuf, OutputError> {
+    // A build folder can be left behind by an earlier run that failed
+    let build_path = get_sibling_path(target_path, "build");
+    if build_path.exists() {
+        check_owned(&build_path, force)?;
+        println!("Removing stale build folder...");
+        remove(&build_path);
+    }
+
+    fs::create_dir_all(&build_path).unwrap();
+    file::write_all_text(
+        build_path.join(MARKER_FILE),
+        "This folder is generated by Shattered Generator, and will be deleted on regeneration\n");
+
+    Ok(build_path)
+}
+
+/// Replaces the target with the finished build.
+pub fn finish_build(target_path: &Path, build_path: &Path) {
+    println!("Moving finished mod to \"{}\"...", target_path.display());
+
+    // Move the old mod out of the way first, so the new one is moved in as a whole
+    let old_path = get_sibling_path(target_path, "old");
+    if old_path.exists() {
+        remove(&old_path);
+    }
+    if target_path.exists() {
+        fs::rename(target_path, &old_path).unwrap();
+    }
+
+    fs::rename(build_path, target_path).unwrap();
+
+    if old_path.exists() {
+        remove(&old_path);
+    }
+}
+
+/// Replaces files in the build that are the same as in the previous output with the previous
+/// files, so unchanged files aren't rewritten when the build replaces the target.
+pub fn reuse_unchanged(target_path: &Path, build_path: &Path, cache: &mut BuildCache) {
+    let mut hashes = Vec::new();
+    let mut reused = 0;
+
+    for relative in file::list_files(build_path) {
+        let key = format!("output:{}", relative.display());
+        let new_file = build_path.join(&relative);
+        let old_file = target_path.join(&relative);
+        let hash = cache::hash_file(&new_file);
+
+        // The cache tells us which files are likely the same, but the file may have been changed
+        // by hand since then so we check it as well
+        if cache.is_unchanged(&key, hash) && old_file.is_file() &&
+            cache::hash_file(&old_file) == hash {
+            file::replace_with_link(&old_file, &new_file);
+            reused += 1;
+        }
+
+        hashes.push((key, hash));
+    }
+
+    cache.clear_prefix("output:");
+    for (key, hash) in hashes {
+        cache.set(&key, hash);
+    }
+
+    println!("Kept {} unchanged files from the previous output", reused);
+}
+
+/// Packs the finished build into an archive next to the target, replacing the old archive.
+pub fn finish_archive(target_path: &Path, build_path: &Path) {
+    let archive_path = get_archive_path(target_path);
+    println!("Packing finished mod into \"{}\"...", archive_path.display());
+
+    // Write the archive under a different name first, so it replaces the old one as a whole
+    let partial_path = get_sibling_path(&archive_path, "build");
+    let mut zip = ZipWriter::new(File::create(&partial_path).unwrap());
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    // The launcher expects the mod's files at the root of the archive
+    for relative in file::list_files(build_path) {
+        let name: Vec<_> = relative.components()
+            .map(|c| c.as_os_str().to_str().unwrap())
+            .collect();
+        zip.start_file(name.join("/"), options).unwrap();
+
+        let mut data = Vec::new();
+        File::open(build_path.join(&relative)).unwrap().read_to_end(&mut data).unwrap();
+        zip.write_all(&data).unwrap();
+    }
+    zip.finish().unwrap();
+
+    fs::rename(&partial_path, &archive_path).unwrap();
+    remove(build_path);
+}
+
+/// Extracts all files in the archive to the directory.
+pub fn extract_archive(archive_path: &Path, target_path: &Path) {
+    let mut archive = ZipArchive::new(File::open(archive_path).unwrap()).unwrap();
+
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i).unwrap();
+        if entry.is_dir() {
+            continue;
+        }
+
+        // Don't extract anything that would end up outside of the target
+        let entry_path = match entry.enclosed_name() {
+            Some(name) => target_path.join(name),
+            None => continue,
+        };
+        fs::create_dir_all(entry_path.parent().unwrap()).unwrap();
+        io::copy(&mut entry, &mut File::create(&entry_path).unwrap()).unwrap();
+    }
+}
+
+pub fn get_archive_path(target_path: &Path) -> PathBuf {
+    let name = target_path.file_name().unwrap().to_str().unwrap();
+    target_path.with_file_name(format!("{}.zip", name))
+}
+
+fn get_sibling_path(path: &Path, suffix: &str) -> PathBuf {
+    let name = path.file_name().unwrap().to_str().unwrap();
+    path.with_file_name(format!(".{}.{}", name, suffix))
+}
+
+fn is_empty_dir(path: &Path) -> bool {
+    path.read_dir().map(|mut entries| entries.next().is_none()).unwrap_or(false)
+}
+
+fn remove(path: &Path) {
+    if path.is_dir() {
+        fs::remove_dir_all(path).unwrap();
+    } else {
+        fs::remove_file(path).unwrap();
+    }
+}
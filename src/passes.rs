@@ -0,0 +1,1328 @@
+//! The transformation passes that turn the game's data into the mod's data.
+
+use std::cmp;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::rc::Rc;
+use palette::Rgb;
+use palette::pixel::Srgb;
+use rand::Rng;
+use config::Config;
+use cultures::Cultures;
+use script::{ScriptError, Scripts};
+use seed::Seed;
+use tags::TagMap;
+use eu4data::{Eu4KeyValue, Eu4Table, Eu4Value};
+use regions::Regions;
+use {clear_events, get_next_valid_tag};
+use {Eu4SourceData, Eu4TargetData, Eu4Localization, Eu4FlagRequest, CountryOrigin, FileTable};
+
+/// The passes that are run when none are configured, in the order they're run in.
+pub const DEFAULT_PASSES: &'static [&'static str] = &[
+    "select_provinces",
+    "province_scripts",
+    "clear_events",
+    "cluster_provinces",
+    "create_countries",
+    "fix_kept_capitals",
+    "handle_natives",
+    "assign_cores",
+    "copy_culture_religion",
+    "set_capitals",
+    "set_technology",
+    "replace_papacy",
+    "generate_rulers",
+    "generate_colors",
+    "fix_electors",
+    "elect_emperor",
+    "remap_diplomacy",
+    "rewrite_tags",
+    "country_scripts",
+];
+
+/// Keys in the history files that refer to a country by its tag.
+pub const TAG_KEYS: &'static [&'static str] = &[
+    "owner", "controller", "add_core", "remove_core", "add_claim", "remove_claim",
+    "add_permanent_claim", "add_historical_friend", "add_historical_rival", "add_truce_with",
+    "add_attacker", "add_defender", "rem_attacker", "rem_defender", "emperor",
+];
+
+/// The date the game starts at, generated rulers take the throne on it.
+pub const START_DATE: &'static str = "1444.11.11";
+
+/// What provinces can be required to have in common to be clustered into one country.
+pub const CLUSTER_RULES: &'static [&'static str] = &["owner", "area", "culture"];
+
+/// What to do with the natives in unowned provinces.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum NativePolicy {
+    /// Leave the natives as they are in the game.
+    Keep,
+    /// Turn every province with natives into a tribal country.
+    Countries,
+    /// Remove the natives, making the provinces empty to colonize.
+    Remove,
+}
+
+impl NativePolicy {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "keep" => Some(NativePolicy::Keep),
+            "countries" => Some(NativePolicy::Countries),
+            "remove" => Some(NativePolicy::Remove),
+            _ => None,
+        }
+    }
+}
+
+/// What to do with the cores other countries have on shattered provinces.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum CorePolicy {
+    /// Leave the cores as they are.
+    Keep,
+    /// Turn the cores into claims. Cores of countries that don't exist at the start are kept so
+    /// they can still be released, those of countries that no longer exist are removed.
+    Claims,
+    /// Remove the cores.
+    Remove,
+}
+
+impl CorePolicy {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "keep" => Some(CorePolicy::Keep),
+            "claims" => Some(CorePolicy::Claims),
+            "remove" => Some(CorePolicy::Remove),
+            _ => None,
+        }
+    }
+}
+
+/// The state passes have access to besides the data they're changing.
+pub struct PassContext<'a> {
+    pub source: &'a Eu4SourceData,
+    pub tag_map: &'a mut TagMap,
+    pub seed: Seed,
+}
+
+/// A single transformation of the mod's data.
+pub trait Pass {
+    /// The name the pass is referred to by in the config.
+    fn name(&self) -> &'static str;
+
+    fn run(&self, data: &mut Eu4TargetData, context: &mut PassContext);
+}
+
+/// Looks up one of the built in passes by its name, script passes run the given scripts.
+pub fn get_pass(name: &str, config: &Config, scripts: &Rc<Scripts>) -> Option<Box<dyn Pass>> {
+    let pass: Box<dyn Pass> = match name {
+        "select_provinces" => Box::new(SelectProvinces {
+            areas: config.shatter_areas.clone(),
+            regions: config.shatter_regions.clone(),
+            superregions: config.shatter_superregions.clone(),
+            tags: config.shatter_tags.clone(),
+            exclude_tags: config.exclude_tags.clone(),
+        }),
+        "province_scripts" => Box::new(ProvinceScripts(scripts.clone())),
+        "clear_events" => Box::new(ClearEvents),
+        "cluster_provinces" => Box::new(ClusterProvinces {
+            size: config.cluster_size,
+            rules: config.cluster_by.clone(),
+        }),
+        "create_countries" => Box::new(CreateCountries),
+        "fix_kept_capitals" => Box::new(FixKeptCapitals),
+        "assign_cores" => Box::new(AssignCores {
+            other_cores: config.other_cores,
+            keep_old_owner_cores: config.keep_old_owner_cores,
+            compatriot_claims: config.compatriot_claims,
+        }),
+        "handle_natives" => Box::new(HandleNatives {
+            policy: config.natives,
+            superregion_policies: config.natives_by_superregion.clone(),
+        }),
+        "copy_culture_religion" => Box::new(CopyCultureReligion),
+        "set_capitals" => Box::new(SetCapitals),
+        "generate_rulers" => Box::new(GenerateRulers {
+            heirs: config.generate_heirs,
+            queens: config.generate_queens,
+        }),
+        "set_technology" => Box::new(SetTechnology {
+            technology_groups: config.technology_groups.clone(),
+            unit_types: config.unit_types.clone(),
+        }),
+        "replace_papacy" => Box::new(ReplacePapacy),
+        "generate_colors" => Box::new(GenerateColors),
+        "fix_electors" => Box::new(FixElectors),
+        "elect_emperor" => Box::new(ElectEmperor),
+        "remap_diplomacy" => Box::new(RemapDiplomacy),
+        "rewrite_tags" => Box::new(RewriteTags {
+            keep_old_owner_cores: config.keep_old_owner_cores,
+        }),
+        "country_scripts" => Box::new(CountryScripts(scripts.clone())),
+        _ => return None,
+    };
+    Some(pass)
+}
+
+/// Gets the passes configured in the config, in the order they should run in.
+pub fn get_passes(config: &Config) -> Result<Vec<Box<dyn Pass>>, ScriptError> {
+    let scripts = Rc::new(Scripts::load(&config.scripts)?);
+    Ok(config.passes.iter()
+        .map(|name| get_pass(name, config, &scripts)
+            .unwrap_or_else(|| panic!("Unknown pass \"{}\"", name)))
+        .collect())
+}
+
+/// Finds the history of the country that had the given tag in the game's data.
+fn get_source_history<'a>(source: &'a Eu4SourceData, tag: &str) -> &'a FileTable {
+    source.country_history.iter().find(|f| f.file_name.starts_with(tag)).unwrap()
+}
+
+/// Finds the data and history of the country that had the given tag in the game's data.
+fn get_source_country<'a>(
+    source: &'a Eu4SourceData, tag: &str
+) -> Option<(&'a FileTable, &'a FileTable)> {
+    let file = source.country_tags.get(tag)?.as_str();
+    let country = source.countries.iter().find(|d| format!("countries/{}", d.file_name) == file)?;
+    let history = source.country_history.iter().find(|f| f.file_name.starts_with(tag))?;
+    Some((country, history))
+}
+
+/// Gets the id and name of a province from its history file's name.
+// TODO: Retrieve the name from the localization file instead of from the file name
+fn get_province_id_and_name(file_name: &str) -> (String, String) {
+    let segments: Vec<_> = file_name
+        .split(|c| c == ' ' || c == '-' || c == '.')
+        .filter(|s| s.len() != 0)
+        .collect();
+    (segments[0].to_string(), segments[1].to_string())
+}
+
+fn get_owner(province: &FileTable) -> Option<&str> {
+    province.data.get("owner").map(|v| v.as_str())
+}
+
+/// Finds the country that takes the place of a country from the game's data, which is the
+/// country that now owns its capital. Countries that didn't own their capital in the game's data
+/// don't exist at the start, and take their own place. Returns None if nobody owns the capital.
+fn get_successor(data: &Eu4TargetData, source: &Eu4SourceData, tag: &str) -> Option<String> {
+    let capital = source.country_history.iter()
+        .find(|f| f.file_name.starts_with(tag))
+        .and_then(|h| h.data.get("capital"))
+        .map(|c| c.as_str());
+    let capital_index = capital.and_then(|capital| source.provinces.iter()
+        .position(|p| get_province_id_and_name(&p.file_name).0 == capital));
+
+    match capital_index {
+        Some(index) if get_owner(&source.provinces[index]) == Some(tag) =>
+            get_owner(&data.provinces[index]).map(|o| o.to_string()),
+        _ => Some(tag.to_string()),
+    }
+}
+
+/// Keeps provinces outside of the configured areas, regions, superregions and tags with their
+/// owners.
+pub struct SelectProvinces {
+    areas: Vec<String>,
+    regions: Vec<String>,
+    superregions: Vec<String>,
+    tags: Vec<String>,
+    exclude_tags: Vec<String>,
+}
+
+impl SelectProvinces {
+    fn in_scope(&self, regions: &Regions, province: &FileTable) -> bool {
+        let (province_id, _) = get_province_id_and_name(&province.file_name);
+        let owner = get_owner(province);
+        let is_in = |list: &Vec<String>, value: Option<&str>| {
+            value.map(|v| list.iter().any(|l| l == v)).unwrap_or(false)
+        };
+
+        let has_places =
+            self.areas.len() != 0 || self.regions.len() != 0 || self.superregions.len() != 0;
+        if has_places &&
+            !is_in(&self.areas, regions.area(&province_id)) &&
+            !is_in(&self.regions, regions.region(&province_id)) &&
+            !is_in(&self.superregions, regions.superregion(&province_id)) {
+            return false;
+        }
+
+        if self.tags.len() != 0 && !is_in(&self.tags, owner) {
+            return false;
+        }
+
+        !is_in(&self.exclude_tags, owner)
+    }
+}
+
+impl Pass for SelectProvinces {
+    fn name(&self) -> &'static str { "select_provinces" }
+
+    fn run(&self, data: &mut Eu4TargetData, context: &mut PassContext) {
+        let regions = context.source.get_regions();
+        let places = self.areas.iter().chain(&self.regions).chain(&self.superregions);
+        for place in places.filter(|p| !regions.exists(p)) {
+            println!("Unknown area, region or superregion \"{}\", it won't match any provinces",
+                place);
+        }
+
+        for (province_index, province) in data.provinces.iter().enumerate() {
+            if !self.in_scope(&regions, province) {
+                data.kept_provinces.insert(province_index);
+            }
+        }
+
+        if data.kept_provinces.len() != 0 {
+            println!("Keeping {} provinces with their owners", data.kept_provinces.len());
+        }
+    }
+}
+
+/// Removes the dated history entries from all provinces.
+pub struct ClearEvents;
+
+impl Pass for ClearEvents {
+    fn name(&self) -> &'static str { "clear_events" }
+
+    fn run(&self, data: &mut Eu4TargetData, _context: &mut PassContext) {
+        println!("Clearing events on provinces...");
+        for (province_index, province) in data.provinces.iter_mut().enumerate() {
+            if !data.kept_provinces.contains(&province_index) {
+                clear_events(&mut province.data);
+            }
+        }
+    }
+}
+
+/// Runs the province function of scripts, which decides which provinces get shattered.
+pub struct ProvinceScripts(Rc<Scripts>);
+
+impl Pass for ProvinceScripts {
+    fn name(&self) -> &'static str { "province_scripts" }
+
+    fn run(&self, data: &mut Eu4TargetData, _context: &mut PassContext) {
+        if self.0.is_empty() {
+            return;
+        }
+
+        println!("Running province scripts...");
+        for (province_index, province) in data.provinces.iter_mut().enumerate() {
+            if !self.0.run_province(province) {
+                data.kept_provinces.insert(province_index);
+            }
+        }
+    }
+}
+
+/// Groups neighbouring provinces into clusters that become a single country.
+pub struct ClusterProvinces {
+    size: usize,
+    rules: Vec<String>,
+}
+
+impl ClusterProvinces {
+    /// Gets what the rules require provinces to have in common to be clustered, or None if the
+    /// province is missing something the rules need.
+    fn cluster_key(&self, regions: &Regions, province: &FileTable) -> Option<Vec<String>> {
+        let (province_id, _) = get_province_id_and_name(&province.file_name);
+
+        self.rules.iter().map(|rule| match rule.as_str() {
+            "owner" => get_owner(province).map(|o| o.to_string()),
+            "area" => regions.area(&province_id).map(|a| a.to_string()),
+            "culture" => province.data.get("culture").map(|v| v.as_str().to_string()),
+            _ => panic!("Unknown cluster rule \"{}\"", rule),
+        }).collect()
+    }
+}
+
+impl Pass for ClusterProvinces {
+    fn name(&self) -> &'static str { "cluster_provinces" }
+
+    fn run(&self, data: &mut Eu4TargetData, context: &mut PassContext) {
+        if self.size <= 1 {
+            return;
+        }
+
+        let map = &context.source.map;
+        if map.is_empty() {
+            println!("No borders between provinces are known, provinces can't be clustered");
+            return;
+        }
+
+        println!("Clustering provinces...");
+        let regions = context.source.get_regions();
+
+        // Only provinces that would otherwise become a country on their own can be clustered,
+        // and only with those that have the same things in common
+        let mut indices = HashMap::new();
+        let mut groups: BTreeMap<Vec<String>, HashSet<String>> = BTreeMap::new();
+        for (province_index, province) in data.provinces.iter().enumerate() {
+            if data.kept_provinces.contains(&province_index) || get_owner(province).is_none() {
+                continue;
+            }
+            if let Some(key) = self.cluster_key(&regions, province) {
+                let (province_id, _) = get_province_id_and_name(&province.file_name);
+                groups.entry(key).or_insert_with(HashSet::new).insert(province_id.clone());
+                indices.insert(province_id, province_index);
+            }
+        }
+
+        // Every connected part of a group is split into clusters, each grown outwards from its
+        // first province in the map's order so the same data always gives the same clusters
+        let mut clustered = HashSet::new();
+        for group in groups.values() {
+            for component in map.connected_components(|id| group.contains(id)) {
+                for first in &component {
+                    if clustered.contains(first) {
+                        continue;
+                    }
+
+                    let cluster: Vec<String> = map
+                        .bfs(first, |id| group.contains(id) && !clustered.contains(id))
+                        .take(self.size)
+                        .collect();
+                    clustered.extend(cluster.iter().cloned());
+
+                    if cluster.len() > 1 {
+                        data.clusters.push(cluster.iter().map(|id| indices[id]).collect());
+                    }
+                }
+            }
+        }
+
+        let clustered_count: usize = data.clusters.iter().map(|c| c.len()).sum();
+        println!("Grouped {} provinces into {} clusters", clustered_count, data.clusters.len());
+    }
+}
+
+/// Creates a new country for every owned province, copied from the province's old owner.
+pub struct CreateCountries;
+
+impl Pass for CreateCountries {
+    fn name(&self) -> &'static str { "create_countries" }
+
+    fn run(&self, data: &mut Eu4TargetData, context: &mut PassContext) {
+        println!("Generating new countries...");
+        let source = context.source;
+        let mut tag_num = 0;
+        // Clustered provinces become part of the country of the cluster's first province
+        let mut members = HashMap::new();
+        for cluster in &data.clusters {
+            for &province_index in cluster {
+                members.insert(province_index, Vec::new());
+            }
+            members.insert(cluster[0], cluster.clone());
+        }
+
+        for province_index in 0..data.provinces.len() {
+            if data.kept_provinces.contains(&province_index) {
+                continue;
+            }
+            let province_members = match members.get(&province_index) {
+                Some(province_members) if province_members.is_empty() => continue,
+                Some(province_members) => province_members.clone(),
+                None => vec![province_index],
+            };
+
+            // Check the province's owner tag, if it has one
+            let old_country_tag = match get_owner(&data.provinces[province_index]) {
+                Some(tag) => tag.to_string(),
+                None => continue, // No owner, we can skip this province
+            };
+
+            // Find the country data for this province
+            let (old_country, old_country_history) =
+                get_source_country(source, &old_country_tag).unwrap();
+
+            add_country(
+                data, context, &mut tag_num, &province_members,
+                old_country.clone(), old_country_history.clone(), old_country_tag);
+        }
+    }
+}
+
+/// Adds a country for the provinces, made from a copy of an existing country's data. The first
+/// province is the country's capital, and the country is named after it.
+fn add_country(
+    data: &mut Eu4TargetData, context: &mut PassContext, tag_num: &mut i32, members: &[usize],
+    mut new_country: FileTable, mut new_country_history: FileTable, old_tag: String
+) {
+    let source = context.source;
+    let capital = &data.provinces[members[0]];
+    let (province_id, province_name) = get_province_id_and_name(&capital.file_name);
+    let new_country_file_name = format!("{}.txt", province_name);
+    new_country.file_name = new_country_file_name.clone();
+
+    // Clear the events on the new country
+    clear_events(&mut new_country.data);
+    clear_events(&mut new_country_history.data);
+
+    // Re-use the tag this province got in an earlier run, unless the game has since started
+    // using that tag itself, otherwise generate a new one and remember it for next time
+    let tag_map = &mut *context.tag_map;
+    let new_country_tag = match tag_map.get(&province_id) {
+        Some(tag) if !source.country_tags.values.iter().any(|v| v.key == tag) => tag.to_string(),
+        previous => {
+            let tag = get_next_valid_tag(tag_num, &data.country_tags, tag_map);
+            if let Some(previous) = previous {
+                println!("Tag {} for {} is now used by the game, reassigned to {}",
+                    previous, province_name, tag);
+            }
+            tag
+        }
+    };
+    tag_map.set(&province_id, &new_country_tag);
+
+    // Add the tag to the tags list
+    data.country_tags.set(
+        &new_country_tag,
+        Eu4Value::String(format!("countries/{}", new_country_file_name)));
+    new_country_history.file_name = format!("{} - {}", new_country_tag, new_country_file_name);
+    data.localizations.push(Eu4Localization {
+        key: new_country_tag.clone(),
+        string: province_name.clone(),
+    });
+
+    // Update the provinces to be owned by the new country
+    let mut province_ids = Vec::new();
+    for &member in members {
+        let province = &mut data.provinces[member];
+        province.data.set("owner", Eu4Value::String(new_country_tag.clone()));
+        province.data.set("controller", Eu4Value::String(new_country_tag.clone()));
+        province_ids.push(get_province_id_and_name(&province.file_name).0);
+    }
+
+    // Every province gets its own seed, so adding or removing provinces doesn't change the
+    // randomly generated data of any of the others
+    data.origins.push(CountryOrigin {
+        tag: new_country_tag,
+        old_tag: old_tag,
+        province: members[0],
+        province_id: province_id.clone(),
+        province_name: province_name,
+        province_ids: province_ids,
+        seed: context.seed.derive(&province_id),
+    });
+
+    // Store the actual data in the lists
+    data.countries.push(new_country);
+    data.country_history.push(new_country_history);
+}
+
+/// Moves the capital of countries that kept some of their provinces if their capital was
+/// shattered.
+pub struct FixKeptCapitals;
+
+impl Pass for FixKeptCapitals {
+    fn name(&self) -> &'static str { "fix_kept_capitals" }
+
+    fn run(&self, data: &mut Eu4TargetData, context: &mut PassContext) {
+        let mut kept_owners: Vec<(&str, String)> = Vec::new();
+        for &province_index in &data.kept_provinces {
+            let province = &data.provinces[province_index];
+            if let Some(owner) = get_owner(province) {
+                if !kept_owners.iter().any(|&(o, _)| o == owner) {
+                    let (province_id, _) = get_province_id_and_name(&province.file_name);
+                    kept_owners.push((owner, province_id));
+                }
+            }
+        }
+
+        for (owner, province_id) in kept_owners {
+            let old_history = get_source_history(context.source, owner);
+            let capital = old_history.data.get("capital").map(|c| c.as_str());
+            let shattered_capital = data.origins.iter()
+                .any(|o| o.old_tag == owner && capital.map(|c| o.owns(c)).unwrap_or(false));
+            if !shattered_capital {
+                continue;
+            }
+
+            let mut history = old_history.clone();
+            history.data.set("capital", Eu4Value::String(province_id.clone()));
+            println!("Moved the capital of {} to {}", owner, province_id);
+            data.kept_country_history.push(history);
+        }
+    }
+}
+
+/// Turns natives in unowned provinces into countries or removes them, depending on the policy of
+/// the superregion the province is in.
+pub struct HandleNatives {
+    policy: NativePolicy,
+    superregion_policies: BTreeMap<String, NativePolicy>,
+}
+
+impl Pass for HandleNatives {
+    fn name(&self) -> &'static str { "handle_natives" }
+
+    fn run(&self, data: &mut Eu4TargetData, context: &mut PassContext) {
+        let all_kept = self.policy == NativePolicy::Keep &&
+            self.superregion_policies.values().all(|p| *p == NativePolicy::Keep);
+        if all_kept {
+            return;
+        }
+
+        println!("Handling natives...");
+        let regions = context.source.get_regions();
+        let cultures = context.source.get_cultures();
+        let owners: HashMap<String, &str> = context.source.provinces.iter()
+            .filter_map(|p| get_owner(p).map(|o| (get_province_id_and_name(&p.file_name).0, o)))
+            .collect();
+        let mut tag_num = 0;
+        let mut created = 0;
+        let mut removed = 0;
+
+        for province_index in 0..data.provinces.len() {
+            let province = &data.provinces[province_index];
+            if data.kept_provinces.contains(&province_index) || get_owner(province).is_some() {
+                continue;
+            }
+            let native_size: f32 = province.data.get("native_size")
+                .and_then(|v| v.as_str().parse().ok())
+                .unwrap_or(0.0);
+            if native_size <= 0.0 {
+                continue;
+            }
+
+            let (province_id, _) = get_province_id_and_name(&province.file_name);
+            let policy = regions.superregion(&province_id)
+                .and_then(|s| self.superregion_policies.get(s))
+                .cloned()
+                .unwrap_or(self.policy);
+            if policy == NativePolicy::Keep {
+                continue;
+            }
+
+            if policy == NativePolicy::Remove {
+                remove_natives(&mut data.provinces[province_index]);
+                removed += 1;
+                continue;
+            }
+
+            // Base the country on one of the same culture if there is one, so it gets the right
+            // graphics, otherwise make one from scratch
+            let (country, mut history, old_tag) =
+                get_native_template(context.source, &cultures, &owners, province);
+            history.data.values.retain(|v| v.key != "add_government_reform" && v.key != "elector");
+            history.data.set("government", Eu4Value::String("tribal".into()));
+
+            let province = &mut data.provinces[province_index];
+            remove_natives(province);
+            province.data.set("is_city", Eu4Value::String("yes".into()));
+            add_country(data, context, &mut tag_num, &[province_index], country, history, old_tag);
+            created += 1;
+        }
+
+        println!("Created {} native countries, removed natives from {} provinces",
+            created, removed);
+    }
+}
+
+fn remove_natives(province: &mut FileTable) {
+    province.data.values.retain(|v| !v.key.starts_with("native_"));
+}
+
+/// Finds the data and history a native country for the province can be made from, the owners are
+/// the tags that own each province in the game's data.
+fn get_native_template(
+    source: &Eu4SourceData, cultures: &Cultures, owners: &HashMap<String, &str>,
+    province: &FileTable
+) -> (FileTable, FileTable, String) {
+    let culture = province.data.get("culture").map(|v| v.as_str()).unwrap_or("");
+
+    let same_culture = source.country_history.iter()
+        .filter(|h| h.data.get("primary_culture").map(|v| v.as_str()) == Some(culture))
+        .filter_map(|h| {
+            let tag = h.file_name.get(0..3)?;
+            get_source_country(source, tag).map(|(country, history)| (country, history, tag))
+        })
+        .next();
+    if let Some((country, history, tag)) = same_culture {
+        return (country.clone(), history.clone(), tag.to_string());
+    }
+
+    // The nearest country is most likely in the same part of the world
+    let (province_id, province_name) = get_province_id_and_name(&province.file_name);
+    let nearest = source.map.bfs(&province_id, |_| true)
+        .filter_map(|id| owners.get(&id).and_then(|tag| get_source_country(source, tag)))
+        .next();
+
+    let mut country = FileTable {
+        file_name: String::new(),
+        data: Eu4Table::new(),
+    };
+    let graphical_culture = cultures.graphical_culture(culture)
+        .map(|g| Eu4Value::String(g.to_string()))
+        .or_else(|| nearest.and_then(|(c, _)| c.data.get("graphical_culture").cloned()));
+    if let Some(graphical_culture) = graphical_culture {
+        country.data.set("graphical_culture", graphical_culture);
+    }
+
+    // The technology group can also be set for the culture in the config
+    let mut history = FileTable {
+        file_name: String::new(),
+        data: Eu4Table::new(),
+    };
+    match nearest.and_then(|(_, h)| h.data.get("technology_group")) {
+        Some(technology_group) => history.data.set("technology_group", technology_group.clone()),
+        None => println!(
+            "No technology group found for the natives in {}, set it for culture {} in \
+             technology_groups", province_name, culture),
+    }
+    (country, history, String::new())
+}
+
+/// Gives new countries cores on their provinces, and decides what happens to the cores other
+/// countries had on them.
+pub struct AssignCores {
+    pub other_cores: CorePolicy,
+    pub keep_old_owner_cores: bool,
+    pub compatriot_claims: bool,
+}
+
+impl Pass for AssignCores {
+    fn name(&self) -> &'static str { "assign_cores" }
+
+    fn run(&self, data: &mut Eu4TargetData, context: &mut PassContext) {
+        println!("Assigning cores...");
+        let source = context.source;
+        let indices: HashMap<String, usize> = data.provinces.iter().enumerate()
+            .map(|(i, p)| (get_province_id_and_name(&p.file_name).0, i))
+            .collect();
+        let owners: HashSet<String> = data.provinces.iter()
+            .filter_map(get_owner)
+            .map(|o| o.to_string())
+            .collect();
+        let source_owners: HashSet<&str> = source.provinces.iter().filter_map(get_owner).collect();
+        let mut claims = 0;
+
+        for origin in &data.origins {
+            for province_id in &origin.province_ids {
+                let index = indices[province_id];
+                let old_owner = get_owner(&source.provinces[index]);
+
+                // Countries that used to own the same neighbouring provinces claim this one
+                let mut compatriots = BTreeSet::new();
+                if self.compatriot_claims && old_owner.is_some() {
+                    for neighbour in source.map.neighbours(province_id) {
+                        let neighbour = match indices.get(neighbour) {
+                            Some(&neighbour) => neighbour,
+                            None => continue,
+                        };
+                        if get_owner(&source.provinces[neighbour]) != old_owner {
+                            continue;
+                        }
+                        if let Some(owner) = get_owner(&data.provinces[neighbour]) {
+                            if owner != origin.tag {
+                                compatriots.insert(owner.to_string());
+                            }
+                        }
+                    }
+                }
+
+                let province = &mut data.provinces[index].data;
+                let mut values: Vec<Eu4KeyValue> = Vec::with_capacity(province.values.len());
+                for entry in province.values.drain(..) {
+                    if entry.key != "add_core" {
+                        values.push(entry);
+                        continue;
+                    }
+
+                    // The new owner's core takes the place of the old owner's
+                    let tag = entry.value.as_str();
+                    if tag == origin.tag || Some(tag) == old_owner {
+                        if Some(tag) == old_owner && self.keep_old_owner_cores {
+                            add_unique(&mut values, "add_core", tag);
+                        }
+                        add_unique(&mut values, "add_core", &origin.tag);
+                        continue;
+                    }
+
+                    match self.other_cores {
+                        CorePolicy::Keep => add_unique(&mut values, "add_core", tag),
+                        CorePolicy::Claims if owners.contains(tag) => {
+                            add_unique(&mut values, "add_claim", tag);
+                            claims += 1;
+                        }
+                        // Countries that don't exist at the start can still be released
+                        CorePolicy::Claims if !source_owners.contains(tag) =>
+                            add_unique(&mut values, "add_core", tag),
+                        _ => {}
+                    }
+                }
+                province.values = values;
+
+                add_unique(&mut province.values, "add_core", &origin.tag);
+                for compatriot in compatriots {
+                    add_unique(&mut province.values, "add_permanent_claim", &compatriot);
+                    claims += 1;
+                }
+            }
+        }
+
+        println!("Added {} claims", claims);
+    }
+}
+
+/// Adds a tag to a list of values, unless the tag is already in it under the same key.
+fn add_unique(values: &mut Vec<Eu4KeyValue>, key: &str, tag: &str) {
+    if !values.iter().any(|v| v.key == key && v.value.as_str() == tag) {
+        values.push(Eu4KeyValue {
+            key: key.to_string(),
+            value: Eu4Value::String(tag.to_string()),
+        });
+    }
+}
+
+/// Makes every country's culture and religion match the province it was generated from.
+pub struct CopyCultureReligion;
+
+impl Pass for CopyCultureReligion {
+    fn name(&self) -> &'static str { "copy_culture_religion" }
+
+    fn run(&self, data: &mut Eu4TargetData, _context: &mut PassContext) {
+        println!("Copying province cultures and religions...");
+        for (origin, history) in data.origins.iter().zip(&mut data.country_history) {
+            let province = &data.provinces[origin.province].data;
+            history.data.set("primary_culture", province.get("culture").unwrap().clone());
+            history.data.set("religion", province.get("religion").unwrap().clone());
+        }
+    }
+}
+
+/// Makes the province a country was generated from its capital, the old country's capital was
+/// copied along with its history.
+pub struct SetCapitals;
+
+impl Pass for SetCapitals {
+    fn name(&self) -> &'static str { "set_capitals" }
+
+    fn run(&self, data: &mut Eu4TargetData, _context: &mut PassContext) {
+        println!("Setting capitals...");
+        for (origin, history) in data.origins.iter().zip(&mut data.country_history) {
+            history.data.set("capital", Eu4Value::String(origin.province_id.clone()));
+            history.data.set("fixed_capital", Eu4Value::String(origin.province_id.clone()));
+        }
+    }
+}
+
+/// Gives every country the technology group and unit type that's most common among the game's
+/// countries of its culture group, unless the config overrides it.
+pub struct SetTechnology {
+    pub technology_groups: BTreeMap<String, String>,
+    pub unit_types: BTreeMap<String, String>,
+}
+
+impl Pass for SetTechnology {
+    fn name(&self) -> &'static str { "set_technology" }
+
+    fn run(&self, data: &mut Eu4TargetData, context: &mut PassContext) {
+        println!("Setting technology groups...");
+        let source = context.source;
+        let cultures = source.get_cultures();
+        let technology_groups = get_most_common_by_group(source, &cultures, "technology_group");
+        let unit_types = get_most_common_by_group(source, &cultures, "unit_type");
+        let mut changed = 0;
+
+        for history in &mut data.country_history {
+            let culture = match history.data.get("primary_culture") {
+                Some(culture) => culture.as_str().to_string(),
+                None => continue,
+            };
+            let group = cultures.group(&culture);
+
+            let settings = [
+                ("technology_group", &self.technology_groups, &technology_groups),
+                ("unit_type", &self.unit_types, &unit_types),
+            ];
+            for &(key, overrides, derived) in &settings {
+                // A culture's override goes before its group's
+                let value = overrides.get(&culture)
+                    .or_else(|| group.and_then(|g| overrides.get(g)))
+                    .or_else(|| group.and_then(|g| derived.get(g)));
+                if let Some(value) = value {
+                    if history.data.get(key).map(|v| v.as_str()) != Some(value.as_str()) {
+                        history.data.set(key, Eu4Value::String(value.clone()));
+                        changed += 1;
+                    }
+                }
+            }
+        }
+
+        println!("Changed {} technology groups and unit types", changed);
+    }
+}
+
+/// Finds the value of a key in country history that's most common for each culture group.
+fn get_most_common_by_group(
+    source: &Eu4SourceData, cultures: &Cultures, key: &str
+) -> HashMap<String, String> {
+    let mut counts: BTreeMap<(&str, &str), usize> = BTreeMap::new();
+    for history in &source.country_history {
+        let culture = history.data.get("primary_culture").map(|v| v.as_str());
+        let group = culture.and_then(|c| cultures.group(c));
+        if let (Some(group), Some(value)) = (group, history.data.get(key)) {
+            *counts.entry((group, value.as_str())).or_insert(0) += 1;
+        }
+    }
+
+    // Ties go to the value that sorts first, so the result doesn't depend on the file order
+    let mut most_common: HashMap<String, (String, usize)> = HashMap::new();
+    for ((group, value), count) in counts {
+        let best = most_common.entry(group.to_string()).or_insert((value.to_string(), count));
+        if count > best.1 {
+            *best = (value.to_string(), count);
+        }
+    }
+    most_common.into_iter().map(|(group, (value, _))| (group, value)).collect()
+}
+
+/// Gives every country a ruler of its own, and optionally an heir and queen, with names from its
+/// culture. Countries whose culture and culture group have no names don't get a ruler, they start
+/// without one. Only monarchies get heirs and queens, so this has to run after the governments
+/// are changed.
+pub struct GenerateRulers {
+    pub heirs: bool,
+    pub queens: bool,
+}
+
+impl Pass for GenerateRulers {
+    fn name(&self) -> &'static str { "generate_rulers" }
+
+    fn run(&self, data: &mut Eu4TargetData, context: &mut PassContext) {
+        println!("Generating rulers...");
+        let cultures = context.source.get_cultures();
+        let start_year: u32 = START_DATE.split('.').next().unwrap().parse().unwrap();
+        let mut generated = 0;
+
+        for (origin, history) in data.origins.iter().zip(&mut data.country_history) {
+            let culture = history.data.get("primary_culture")
+                .map(|c| c.as_str().to_string())
+                .unwrap_or_else(String::new);
+            let male_names = cultures.names(&culture, "male_names");
+            if male_names.is_empty() {
+                continue;
+            }
+            let female_names = cultures.names(&culture, "female_names");
+            let mut dynasty_names = cultures.names(&culture, "dynasty_names");
+            if dynasty_names.is_empty() {
+                dynasty_names.push(&origin.province_name);
+            }
+
+            // Republics, theocracies and native tribes can't have heirs or queens
+            let monarchy = history.data.get("government")
+                .map(|g| g.as_str().contains("monarchy"))
+                .unwrap_or(false);
+
+            let mut rand = origin.seed.derive("rulers").rng();
+            let dynasty = *rand.choose(&dynasty_names).unwrap();
+            let mut rulers = Eu4Table::new();
+
+            let age = rand.gen_range(16, 60);
+            let monarch = generate_person(&mut rand, &male_names, dynasty, start_year - age);
+            rulers.values.push(Eu4KeyValue { key: "monarch".into(), value: monarch });
+
+            // A queen comes from another dynasty, if there is one
+            if self.queens && monarchy && !female_names.is_empty() {
+                let other_dynasties: Vec<&str> = dynasty_names.iter()
+                    .cloned()
+                    .filter(|&d| d != dynasty)
+                    .collect();
+                let queen_dynasty = *rand.choose(&other_dynasties).unwrap_or(&dynasty);
+                let queen_age = rand.gen_range(16, age + 1);
+                let mut queen = generate_person(
+                    &mut rand, &female_names, queen_dynasty, start_year - queen_age);
+                if let Eu4Value::Table(ref mut queen) = queen {
+                    queen.set("country_of_origin", Eu4Value::String(origin.tag.clone()));
+                    queen.set("female", Eu4Value::String("yes".into()));
+                }
+                rulers.values.push(Eu4KeyValue { key: "queen".into(), value: queen });
+            }
+
+            if self.heirs && monarchy {
+                // The heir is the monarch's child
+                let heir_age = rand.gen_range(0, cmp::min(16, age - 15));
+                let mut heir =
+                    generate_person(&mut rand, &male_names, dynasty, start_year - heir_age);
+                if let Eu4Value::Table(ref mut heir) = heir {
+                    heir.set("death_date", Eu4Value::String(format!("{}.1.1", start_year + 60)));
+                    heir.set("claim", Eu4Value::String(rand.gen_range(50, 101).to_string()));
+                }
+                rulers.values.push(Eu4KeyValue { key: "heir".into(), value: heir });
+            }
+
+            // The old country's rulers don't apply anymore
+            history.data.values
+                .retain(|v| v.key != "monarch" && v.key != "queen" && v.key != "heir");
+            history.data.values.push(Eu4KeyValue {
+                key: START_DATE.into(),
+                value: Eu4Value::Table(rulers),
+            });
+            generated += 1;
+        }
+
+        println!("Generated rulers for {} countries", generated);
+    }
+}
+
+/// Generates a person with a random name from the list and random stats.
+fn generate_person<R: Rng>(
+    rand: &mut R, names: &[&str], dynasty: &str, birth_year: u32
+) -> Eu4Value {
+    let mut person = Eu4Table::new();
+    person.set("name", Eu4Value::String(rand.choose(names).unwrap().to_string()));
+    person.set("dynasty", Eu4Value::String(dynasty.to_string()));
+    person.set("birth_date", Eu4Value::String(format!("{}.1.1", birth_year)));
+    for stat in &["adm", "dip", "mil"] {
+        person.set(stat, Eu4Value::String(rand.gen_range(0, 7).to_string()));
+    }
+    Eu4Value::Table(person)
+}
+
+/// Replaces papacies with theocratic governments, there can only be one pope.
+pub struct ReplacePapacy;
+
+impl Pass for ReplacePapacy {
+    fn name(&self) -> &'static str { "replace_papacy" }
+
+    fn run(&self, data: &mut Eu4TargetData, _context: &mut PassContext) {
+        println!("Replacing papacies...");
+        for history in &mut data.country_history {
+            if history.data.get("government").unwrap().as_str() == "papal_government" {
+                history.data.set(
+                    "government", Eu4Value::String("theocratic_government".into()));
+            }
+        }
+    }
+}
+
+/// Generates a color for every country, and requests a flag in those colors.
+pub struct GenerateColors;
+
+impl Pass for GenerateColors {
+    fn name(&self) -> &'static str { "generate_colors" }
+
+    fn run(&self, data: &mut Eu4TargetData, _context: &mut PassContext) {
+        println!("Generating colors...");
+        for (origin, country) in data.origins.iter().zip(&mut data.countries) {
+            // TODO: Improve color generation
+            let mut rand = origin.seed.derive("color").rng();
+            let color: [u8; 3] = [rand.gen(), rand.gen(), rand.gen()];
+            let color_alt: [u8; 3] = [rand.gen(), rand.gen(), rand.gen()];
+            country.data.set("color", Eu4Value::color(color[0], color[1], color[2]));
+            data.flag_requests.push(Eu4FlagRequest {
+                tag: origin.tag.clone(),
+                color: Rgb::from(Srgb::from_pixel(&color)),
+                color_alt: Rgb::from(Srgb::from_pixel(&color_alt)),
+                seed: origin.seed.derive("flag"),
+            });
+        }
+    }
+}
+
+/// Fixes the HRE electors, a country only stays an elector if it has the old country's capital.
+pub struct FixElectors;
+
+impl Pass for FixElectors {
+    fn name(&self) -> &'static str { "fix_electors" }
+
+    fn run(&self, data: &mut Eu4TargetData, context: &mut PassContext) {
+        println!("Fixing electors...");
+        for (origin, history) in data.origins.iter().zip(&mut data.country_history) {
+            if !history.data.get("elector").map(|v| v.as_str() == "yes").unwrap_or(false) {
+                continue;
+            }
+
+            let old_history = &get_source_history(context.source, &origin.old_tag).data;
+            if !origin.owns(old_history.get("capital").unwrap().as_str()) {
+                history.data.set("elector", Eu4Value::String("no".into()));
+            } else {
+                println!("Granted elector status to {}", origin.province_name);
+            }
+        }
+    }
+}
+
+/// Elects the country with the capital of HAB (Austria) as emperor.
+pub struct ElectEmperor;
+
+impl Pass for ElectEmperor {
+    fn name(&self) -> &'static str { "elect_emperor" }
+
+    fn run(&self, data: &mut Eu4TargetData, context: &mut PassContext) {
+        println!("Electing emperor...");
+        for origin in data.origins.iter().filter(|o| o.old_tag == "HAB") {
+            let old_history = &get_source_history(context.source, &origin.old_tag).data;
+            if origin.owns(old_history.get("capital").unwrap().as_str()) {
+                data.hre_history.set("1437.12.9", Eu4Value::String(origin.tag.clone()));
+                data.hre_history.set("1806.7.12", Eu4Value::String("xxx".into()));
+                println!("Granted emperor status to {}", origin.province_name);
+            }
+        }
+    }
+}
+
+/// Runs the country function of scripts for every new country.
+pub struct CountryScripts(Rc<Scripts>);
+
+impl Pass for CountryScripts {
+    fn name(&self) -> &'static str { "country_scripts" }
+
+    fn run(&self, data: &mut Eu4TargetData, _context: &mut PassContext) {
+        if self.0.is_empty() {
+            return;
+        }
+
+        println!("Running country scripts...");
+        for (i, origin) in data.origins.iter().enumerate() {
+            self.0.run_country(
+                &mut data.countries[i], &mut data.country_history[i],
+                &data.provinces[origin.province]);
+        }
+    }
+}
+
+/// Remaps the relationships in the game's diplomacy files to the countries that took the place of
+/// the countries in them, relationships that can't be remapped are removed.
+pub struct RemapDiplomacy;
+
+impl Pass for RemapDiplomacy {
+    fn name(&self) -> &'static str { "remap_diplomacy" }
+
+    fn run(&self, data: &mut Eu4TargetData, context: &mut PassContext) {
+        println!("Remapping diplomacy...");
+        let mut successors: HashMap<String, Option<String>> = HashMap::new();
+        let mut remapped = 0;
+        let mut removed = 0;
+        let mut changed_files = Vec::new();
+
+        for file in &context.source.diplomacy {
+            let mut new_file = FileTable {
+                file_name: file.file_name.clone(),
+                data: Eu4Table::new(),
+            };
+
+            for entry in &file.data.values {
+                // Relationships are tables of the two countries in it along with the dates
+                let relationship = match &entry.value {
+                    &Eu4Value::Table(ref table)
+                        if table.get("first").is_some() && table.get("second").is_some() => table,
+                    _ => {
+                        new_file.data.values.push(entry.clone());
+                        continue;
+                    }
+                };
+
+                let mut tags = Vec::new();
+                for key in &["first", "second"] {
+                    let tag = relationship.get(key).unwrap().as_str();
+                    let successor = successors.entry(tag.to_string())
+                        .or_insert_with(|| get_successor(data, context.source, tag));
+                    tags.push((tag, successor.clone()));
+                }
+
+                // A country can't have a relationship with itself
+                let (first, second) = match (&tags[0].1, &tags[1].1) {
+                    (&Some(ref first), &Some(ref second)) if first != second => (first, second),
+                    _ => {
+                        removed += 1;
+                        continue;
+                    }
+                };
+
+                let mut new_relationship = relationship.clone();
+                if first != tags[0].0 || second != tags[1].0 {
+                    new_relationship.set("first", Eu4Value::String(first.clone()));
+                    new_relationship.set("second", Eu4Value::String(second.clone()));
+                    remapped += 1;
+                }
+                new_file.data.values.push(Eu4KeyValue {
+                    key: entry.key.clone(),
+                    value: Eu4Value::Table(new_relationship),
+                });
+            }
+
+            // Files that stay the same don't need to be in the mod
+            if new_file.data.serialize() != file.data.serialize() {
+                changed_files.push(new_file);
+            }
+        }
+
+        println!("Remapped {} relationships, removed {}", remapped, removed);
+        data.diplomacy.extend(changed_files);
+    }
+}
+
+/// Rewrites references in the history files to tags that no longer own anything, references in
+/// a province go to its new owner and others go to the country that owns the tag's old capital.
+/// References that can't be rewritten are removed, as are claims of a province's own owner.
+///
+/// The `first` and `second` countries of relationships in the diplomacy files aren't rewritten
+/// here, a relationship has to be remapped or removed as a whole. That's done by
+/// `RemapDiplomacy`, which has to run before this pass.
+pub struct RewriteTags {
+    /// If the old owner's cores were kept by `AssignCores`, these aren't moved to the new owner.
+    pub keep_old_owner_cores: bool,
+}
+
+impl Pass for RewriteTags {
+    fn name(&self) -> &'static str { "rewrite_tags" }
+
+    fn run(&self, data: &mut Eu4TargetData, context: &mut PassContext) {
+        println!("Rewriting references to removed tags...");
+        let source = context.source;
+
+        // Tags are removed if they owned provinces in the game's data but don't anymore
+        let remaining: HashSet<&str> = data.provinces.iter().filter_map(get_owner).collect();
+        let removed: HashSet<String> = source.provinces.iter()
+            .filter_map(get_owner)
+            .filter(|tag| !remaining.contains(tag))
+            .map(|tag| tag.to_string())
+            .collect();
+        if removed.is_empty() {
+            println!("No tags were removed");
+            return;
+        }
+
+        let successors: HashMap<String, Option<String>> = removed.iter()
+            .map(|tag| {
+                let successor = get_successor(data, source, tag)
+                    .filter(|successor| !removed.contains(successor));
+                (tag.clone(), successor)
+            })
+            .collect();
+
+        let mut report = RewriteReport::default();
+
+        for (province, source_province) in data.provinces.iter_mut().zip(&source.provinces) {
+            let owner = get_owner(province).map(|o| o.to_string());
+            let kept_core = if self.keep_old_owner_cores {
+                get_owner(source_province)
+            } else {
+                None
+            };
+            report.rewrite(province, |key, tag| {
+                if !removed.contains(tag) || (key == "add_core" && Some(tag) == kept_core) {
+                    None
+                } else if key == "add_claim" || key == "add_permanent_claim" {
+                    // The owner can't claim its own province
+                    Some(None)
+                } else {
+                    Some(owner.clone())
+                }
+            });
+        }
+
+        // The HRE history only has the new emperor in it, so it doesn't need to be rewritten
+        let successor = |_: &str, tag: &str| successors.get(tag).cloned();
+        for file in data.country_history.iter_mut()
+            .chain(data.kept_country_history.iter_mut())
+            .chain(data.diplomacy.iter_mut())
+            .chain(data.wars.iter_mut()) {
+            report.rewrite(file, successor);
+        }
+
+        // The game's own files only end up in the mod if something in them changed, the history
+        // of removed tags doesn't matter anymore
+        let removed_history = |file: &FileTable| {
+            removed.iter().any(|tag| file.file_name.starts_with(tag.as_str()))
+        };
+        report.rewrite_source(
+            &source.country_history, &mut data.kept_country_history, removed_history, successor);
+        report.rewrite_source(&source.diplomacy, &mut data.diplomacy, |_| false, successor);
+        report.rewrite_source(&source.wars, &mut data.wars, |_| false, successor);
+
+        report.print(removed.len());
+    }
+}
+
+/// Keeps track of what `RewriteTags` changed.
+#[derive(Default)]
+struct RewriteReport {
+    /// How many references were remapped and removed, by key.
+    keys: BTreeMap<String, (usize, usize)>,
+    files: usize,
+}
+
+impl RewriteReport {
+    /// Rewrites a file, the successor of a tag under a key is None if it wasn't removed, or the
+    /// tag to use instead if there is one.
+    fn rewrite<F>(&mut self, file: &mut FileTable, successor: F) -> bool
+        where F: Fn(&str, &str) -> Option<Option<String>>
+    {
+        let changed = self.rewrite_table(&mut file.data, &successor);
+        if changed {
+            self.files += 1;
+        }
+        changed
+    }
+
+    fn rewrite_table<F>(&mut self, table: &mut Eu4Table, successor: &F) -> bool
+        where F: Fn(&str, &str) -> Option<Option<String>>
+    {
+        let mut changed = false;
+        let mut values: Vec<Eu4KeyValue> = Vec::with_capacity(table.values.len());
+
+        for mut entry in table.values.drain(..) {
+            // Tags can be in nested tables, like the events in province history
+            let replacement = match entry.value {
+                Eu4Value::Table(ref mut nested) => {
+                    changed |= self.rewrite_table(nested, successor);
+                    None
+                }
+                Eu4Value::String(ref tag) if TAG_KEYS.contains(&entry.key.as_str()) =>
+                    successor(&entry.key, tag),
+                _ => None,
+            };
+
+            if let Some(replacement) = replacement {
+                changed = true;
+                let counts = self.keys.entry(entry.key.clone()).or_insert((0, 0));
+                match replacement {
+                    // The new tag may already be there, like a core of the new owner
+                    Some(tag) => {
+                        counts.0 += 1;
+                        if values.iter().any(|v| v.key == entry.key && v.value.as_str() == tag) {
+                            continue;
+                        }
+                        entry.value = Eu4Value::String(tag);
+                    }
+                    None => {
+                        counts.1 += 1;
+                        continue;
+                    }
+                }
+            }
+
+            values.push(entry);
+        }
+
+        table.values = values;
+        changed
+    }
+
+    /// Rewrites the game's files that aren't in the mod yet, and adds those that changed to it.
+    fn rewrite_source<S, F>(
+        &mut self, source_files: &[FileTable], target_files: &mut Vec<FileTable>, skip: S,
+        successor: F
+    )
+        where S: Fn(&FileTable) -> bool, F: Fn(&str, &str) -> Option<Option<String>>
+    {
+        for file in source_files {
+            if skip(file) || target_files.iter().any(|f| f.file_name == file.file_name) {
+                continue;
+            }
+
+            let mut file = file.clone();
+            if self.rewrite(&mut file, &successor) {
+                target_files.push(file);
+            }
+        }
+    }
+
+    fn print(&self, removed_count: usize) {
+        println!("Rewrote references to {} removed tags in {} files", removed_count, self.files);
+        for (key, &(remapped, removed)) in &self.keys {
+            println!("  {}: {} remapped, {} removed", key, remapped, removed);
+        }
+    }
+}
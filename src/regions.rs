@@ -0,0 +1,103 @@
+use std::collections::{HashMap, HashSet};
+use eu4data::{Eu4Table, Eu4Value};
+
+/// The areas, regions and superregions provinces are grouped into.
+pub struct Regions {
+    /// The area of every province, by province id.
+    areas: HashMap<String, String>,
+    /// The region of every area.
+    regions: HashMap<String, String>,
+    /// The superregion of every region.
+    superregions: HashMap<String, String>,
+    /// The names of all areas, regions and superregions, including those without any members or
+    /// that aren't part of a larger group.
+    names: HashSet<String>,
+}
+
+impl Regions {
+    /// Reads the regions from the parsed map/area.txt, map/region.txt and map/superregion.txt.
+    pub fn new(areas: &Eu4Table, regions: &Eu4Table, superregions: &Eu4Table) -> Self {
+        Regions {
+            areas: get_parents(areas, |v| Some(v)),
+            regions: get_parents(regions, |v| match v {
+                &Eu4Value::Table(ref table) => table.get("areas"),
+                _ => None,
+            }),
+            superregions: get_parents(superregions, |v| Some(v)),
+            names: areas.values.iter().chain(&regions.values).chain(&superregions.values)
+                .map(|group| group.key.clone())
+                .collect(),
+        }
+    }
+
+    pub fn area(&self, province_id: &str) -> Option<&str> {
+        self.areas.get(province_id).map(|a| a.as_str())
+    }
+
+    pub fn region(&self, province_id: &str) -> Option<&str> {
+        self.area(province_id).and_then(|a| self.regions.get(a)).map(|r| r.as_str())
+    }
+
+    pub fn superregion(&self, province_id: &str) -> Option<&str> {
+        self.region(province_id).and_then(|r| self.superregions.get(r)).map(|s| s.as_str())
+    }
+
+    /// Checks if an area, region or superregion with the given name exists.
+    pub fn exists(&self, name: &str) -> bool {
+        self.names.contains(name)
+    }
+}
+
+/// Maps every member of the groups in the table to the group it's in, the members of a group
+/// are the keyless strings in the value returned by `members`.
+fn get_parents<F>(table: &Eu4Table, members: F) -> HashMap<String, String>
+    where F: Fn(&Eu4Value) -> Option<&Eu4Value>
+{
+    let mut parents = HashMap::new();
+
+    for group in &table.values {
+        let values: Vec<&Eu4Value> = match members(&group.value) {
+            Some(&Eu4Value::Array(ref values)) => values.iter().collect(),
+            // Groups that also have keyed values, like a color, are parsed as tables
+            Some(&Eu4Value::Table(ref table)) => table.values.iter()
+                .filter(|v| v.key.len() == 0)
+                .map(|v| &v.value)
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        for value in values {
+            if let &Eu4Value::String(ref member) = value {
+                parents.insert(member.clone(), group.key.clone());
+            }
+        }
+    }
+
+    parents
+}
+
+#[cfg(test)]
+mod tests {
+    use eu4data::Eu4Table;
+    use super::Regions;
+
+    #[test]
+    fn province_groups() {
+        let areas = Eu4Table::parse(
+            "svealand_area = { 1 2 }\nskane_area = { color = { 1 2 3 } 12 }\nempty_area = { }");
+        let regions = Eu4Table::parse(
+            "scandinavia_region = { areas = { svealand_area skane_area } }\n\
+             baltic_region = { areas = { empty_area } }");
+        let superregions = Eu4Table::parse("europe_superregion = { scandinavia_region }");
+        let regions = Regions::new(&areas, &regions, &superregions);
+
+        assert_eq!(regions.area("12"), Some("skane_area"));
+        assert_eq!(regions.region("2"), Some("scandinavia_region"));
+        assert_eq!(regions.superregion("1"), Some("europe_superregion"));
+        assert_eq!(regions.area("3"), None);
+        assert!(regions.exists("europe_superregion"));
+        assert!(regions.exists("baltic_region"));
+        assert!(regions.exists("empty_area"));
+        assert!(!regions.exists("atlantis_region"));
+    }
+}
@@ -0,0 +1,227 @@
+//! Runs user scripts written in Rhai that customize how provinces are shattered.
+//!
+//! A script can define either or both of these functions:
+//!
+//! ```rhai
+//! // Called for every province before countries are created, return false to keep the province
+//! // with its current owner instead of making it into a country
+//! fn province(province) { }
+//!
+//! // Called for every new country, after the built in passes before it have run
+//! fn country(country, history, province) { }
+//! ```
+//!
+//! The arguments are tables with `get`, `get_all`, `set`, `add`, `has`, `remove` and `keys`
+//! methods, and a `file_name` property. Changes made to them end up in the generated mod.
+
+use std::cell::RefCell;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::PathBuf;
+use std::rc::Rc;
+use rhai::{Array, CallFnOptions, Dynamic, Engine, EvalAltResult, Scope, AST};
+use eu4data::{Eu4KeyValue, Eu4Table, Eu4Value};
+use FileTable;
+
+#[derive(Debug)]
+pub enum ScriptError {
+    /// The script could not be read.
+    Io(PathBuf, io::Error),
+    /// The script is not valid Rhai.
+    Parse(PathBuf, String),
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &ScriptError::Io(ref path, ref error) =>
+                write!(f, "Could not read script at \"{}\": {}", path.display(), error),
+            &ScriptError::Parse(ref path, ref error) =>
+                write!(f, "Script \"{}\" contains errors: {}", path.display(), error),
+        }
+    }
+}
+
+/// The scripts listed in the config, ready to be run.
+pub struct Scripts {
+    engine: Engine,
+    scripts: Vec<(PathBuf, AST)>,
+}
+
+impl Scripts {
+    pub fn load(paths: &[PathBuf]) -> Result<Self, ScriptError> {
+        let mut engine = Engine::new();
+        engine.register_type_with_name::<ScriptTable>("Table")
+            .register_get("file_name", ScriptTable::file_name)
+            .register_fn("get", ScriptTable::get)
+            .register_fn("get_all", ScriptTable::get_all)
+            .register_fn("set", ScriptTable::set)
+            .register_fn("add", ScriptTable::add)
+            .register_fn("has", ScriptTable::has)
+            .register_fn("remove", ScriptTable::remove)
+            .register_fn("keys", ScriptTable::keys);
+
+        let mut scripts = Vec::new();
+        for path in paths {
+            println!("Loading script \"{}\"...", path.display());
+            let mut text = String::new();
+            File::open(path)
+                .and_then(|mut f| f.read_to_string(&mut text))
+                .map_err(|e| ScriptError::Io(path.clone(), e))?;
+            let ast = engine.compile(&text)
+                .map_err(|e| ScriptError::Parse(path.clone(), e.to_string()))?;
+            scripts.push((path.clone(), ast));
+        }
+
+        Ok(Scripts {
+            engine: engine,
+            scripts: scripts,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scripts.is_empty()
+    }
+
+    /// Runs the province function of all scripts, returns false if any of them decided the
+    /// province should be kept by its owner.
+    pub fn run_province(&self, province: &mut FileTable) -> bool {
+        let province_table = ScriptTable::new(province.clone());
+
+        let mut shatter = true;
+        for result in self.call("province", vec![Dynamic::from(province_table.clone())]) {
+            shatter &= result.as_bool().unwrap_or(true);
+        }
+
+        *province = province_table.into_inner();
+        shatter
+    }
+
+    /// Runs the country function of all scripts for a newly created country.
+    pub fn run_country(
+        &self, country: &mut FileTable, history: &mut FileTable, province: &FileTable
+    ) {
+        let country_table = ScriptTable::new(country.clone());
+        let history_table = ScriptTable::new(history.clone());
+        let province_table = ScriptTable::new(province.clone());
+
+        self.call("country", vec![
+            Dynamic::from(country_table.clone()),
+            Dynamic::from(history_table.clone()),
+            Dynamic::from(province_table),
+        ]);
+
+        *country = country_table.into_inner();
+        *history = history_table.into_inner();
+    }
+
+    /// Calls the function with the given name in every script that defines it.
+    fn call(&self, name: &str, args: Vec<Dynamic>) -> Vec<Dynamic> {
+        let mut results = Vec::new();
+        for &(ref path, ref ast) in &self.scripts {
+            let defined = ast.iter_functions()
+                .any(|f| f.name == name && f.params.len() == args.len());
+            if !defined {
+                continue;
+            }
+
+            // Only the function is run, not the script's top level code
+            let options = CallFnOptions::new().eval_ast(false);
+            let result = self.engine.call_fn_with_options::<Dynamic>(
+                options, &mut Scope::new(), ast, name, args.clone()
+            ).unwrap_or_else(|e| panic!("Script \"{}\" failed: {}", path.display(), e));
+            results.push(result);
+        }
+        results
+    }
+}
+
+/// A table shared with scripts, changes scripts make to it are visible after they've run.
+#[derive(Clone)]
+struct ScriptTable(Rc<RefCell<FileTable>>);
+
+impl ScriptTable {
+    fn new(table: FileTable) -> Self {
+        ScriptTable(Rc::new(RefCell::new(table)))
+    }
+
+    fn into_inner(self) -> FileTable {
+        self.0.borrow().clone()
+    }
+
+    fn file_name(&mut self) -> String {
+        self.0.borrow().file_name.clone()
+    }
+
+    fn get(&mut self, key: &str) -> Dynamic {
+        self.0.borrow().data.get(key).map(to_dynamic).unwrap_or(Dynamic::UNIT)
+    }
+
+    fn get_all(&mut self, key: &str) -> Array {
+        self.0.borrow().data.values.iter()
+            .filter(|v| v.key == key)
+            .map(|v| to_dynamic(&v.value))
+            .collect()
+    }
+
+    fn set(&mut self, key: &str, value: Dynamic) -> Result<(), Box<EvalAltResult>> {
+        let value = from_dynamic(value)?;
+        self.0.borrow_mut().data.set(key, value);
+        Ok(())
+    }
+
+    /// Adds a value even if the key already exists, for keys like add_core that can repeat.
+    fn add(&mut self, key: &str, value: Dynamic) -> Result<(), Box<EvalAltResult>> {
+        let value = from_dynamic(value)?;
+        self.0.borrow_mut().data.values.push(Eu4KeyValue { key: key.into(), value: value });
+        Ok(())
+    }
+
+    fn has(&mut self, key: &str) -> bool {
+        self.0.borrow().data.get(key).is_some()
+    }
+
+    fn remove(&mut self, key: &str) {
+        self.0.borrow_mut().data.values.retain(|v| v.key != key);
+    }
+
+    fn keys(&mut self) -> Array {
+        self.0.borrow().data.values.iter()
+            .filter(|v| v.key.len() != 0)
+            .map(|v| Dynamic::from(v.key.clone()))
+            .collect()
+    }
+}
+
+/// Converts a value to something scripts can use, nested tables are copies.
+fn to_dynamic(value: &Eu4Value) -> Dynamic {
+    match value {
+        &Eu4Value::String(ref value) => Dynamic::from(value.clone()),
+        &Eu4Value::Array(ref values) =>
+            Dynamic::from_array(values.iter().map(to_dynamic).collect()),
+        &Eu4Value::Table(ref table) => Dynamic::from(ScriptTable::new(FileTable {
+            file_name: String::new(),
+            data: table.clone(),
+        })),
+    }
+}
+
+fn from_dynamic(value: Dynamic) -> Result<Eu4Value, Box<EvalAltResult>> {
+    if value.is::<ScriptTable>() {
+        let table: Eu4Table = value.cast::<ScriptTable>().into_inner().data;
+        return Ok(Eu4Value::Table(table));
+    }
+    if value.is_array() {
+        let values: Result<_, _> = value.into_array()?.into_iter().map(from_dynamic).collect();
+        return Ok(Eu4Value::Array(values?));
+    }
+    if let Ok(value) = value.as_bool() {
+        return Ok(Eu4Value::String(if value { "yes" } else { "no" }.into()));
+    }
+    if value.is_string() || value.is_int() || value.is_float() {
+        return Ok(Eu4Value::String(value.to_string()));
+    }
+
+    Err(format!("Can't store a {} in a table", value.type_name()).into())
+}
@@ -0,0 +1,38 @@
+use rand::{self, SeedableRng, StdRng};
+
+/// A seed for generating random values, seeds for individual parts of the generation can be
+/// derived from it so changing one part doesn't reshuffle everything else.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Seed(pub u64);
+
+impl Seed {
+    /// Picks a random seed, for when none has been configured. TOML numbers are signed, so the
+    /// seed is kept in that range to allow putting it in the config to reproduce the run.
+    pub fn random() -> Seed {
+        Seed(rand::random::<u64>() >> 1)
+    }
+
+    /// Derives a new seed for the part of the generation identified by the key.
+    pub fn derive(&self, key: &str) -> Seed {
+        // FNV-1a over the key, starting from our own seed
+        let mut hash = self.0 ^ 0xcbf29ce484222325;
+        for byte in key.bytes() {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+
+        Seed(mix(hash))
+    }
+
+    pub fn rng(&self) -> StdRng {
+        let seed = [self.0 as usize, (self.0 >> 32) as usize];
+        StdRng::from_seed(&seed[..])
+    }
+}
+
+/// Finalizer from splitmix64, spreads similar hashes out over the full range.
+fn mix(mut value: u64) -> u64 {
+    value = (value ^ (value >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    value = (value ^ (value >> 27)).wrapping_mul(0x94d049bb133111eb);
+    value ^ (value >> 31)
+}
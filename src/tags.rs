@@ -0,0 +1,51 @@
+use std::path::Path;
+use eu4data::{Eu4Table, Eu4Value};
+use file;
+
+/// A persisted mapping of province ids to the tags generated for them, used to keep tags stable
+/// across regenerations.
+pub struct TagMap {
+    table: Eu4Table,
+}
+
+impl TagMap {
+    pub fn load<P: AsRef<Path>>(path: P) -> Self {
+        let path = path.as_ref();
+        println!("Loading tag map at \"{}\"...", path.display());
+
+        // If there's no tag map yet this is the first run, so we start out with an empty one
+        let table = if path.is_file() {
+            Eu4Table::parse(&file::read_all_text(path))
+        } else {
+            println!("No tag map found, starting a new one");
+            Eu4Table::new()
+        };
+
+        TagMap {
+            table: table
+        }
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) {
+        let path = path.as_ref();
+        println!("Saving tag map at \"{}\"...", path.display());
+        file::write_all_text(path, &self.serialize());
+    }
+
+    pub fn serialize(&self) -> String {
+        self.table.serialize()
+    }
+
+    pub fn get(&self, province_id: &str) -> Option<&str> {
+        self.table.get(province_id).map(|v| v.as_str())
+    }
+
+    pub fn set(&mut self, province_id: &str, tag: &str) {
+        self.table.set(province_id, Eu4Value::String(tag.into()));
+    }
+
+    /// Checks if the tag has been reserved for any province.
+    pub fn contains_tag(&self, tag: &str) -> bool {
+        self.table.values.iter().any(|v| v.value.as_str() == tag)
+    }
+}
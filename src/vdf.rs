@@ -0,0 +1,153 @@
+//! A parser for Valve's KeyValues format, as used by Steam's libraryfolders.vdf.
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum VdfValue {
+    String(String),
+    Table(Vec<(String, VdfValue)>),
+}
+
+impl VdfValue {
+    pub fn parse(text: &str) -> Result<VdfValue, String> {
+        let mut tokens = tokenize(text)?.into_iter();
+        let table = parse_table(&mut tokens, false)?;
+        Ok(VdfValue::Table(table))
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        if let &VdfValue::String(ref val) = self {
+            Some(val)
+        } else {
+            None
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&VdfValue> {
+        if let &VdfValue::Table(ref values) = self {
+            // Keys in KeyValues files are case insensitive
+            values.iter().find(|v| v.0.eq_ignore_ascii_case(key)).map(|v| &v.1)
+        } else {
+            None
+        }
+    }
+
+    pub fn entries(&self) -> &[(String, VdfValue)] {
+        if let &VdfValue::Table(ref values) = self {
+            values
+        } else {
+            &[]
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    String(String),
+    Open,
+    Close,
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' => tokens.push(Token::Open),
+            '}' => tokens.push(Token::Close),
+            '"' => {
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some('n') => value.push('\n'),
+                            Some('t') => value.push('\t'),
+                            Some(c) => value.push(c),
+                            None => return Err("Unexpected end of file in escape".into()),
+                        },
+                        Some(c) => value.push(c),
+                        None => return Err("Unexpected end of file in string".into()),
+                    }
+                }
+                tokens.push(Token::String(value));
+            },
+            '/' if chars.peek() == Some(&'/') => {
+                while chars.peek().map(|c| *c != '\n').unwrap_or(false) {
+                    chars.next();
+                }
+            },
+            c if c.is_whitespace() => {},
+            c => {
+                // Unquoted strings run until the next whitespace or brace
+                let mut value = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || c == '{' || c == '}' || c == '"' {
+                        break;
+                    }
+                    value.push(c);
+                    chars.next();
+                }
+                tokens.push(Token::String(value));
+            },
+        }
+    }
+
+    Ok(tokens)
+}
+
+fn parse_table<I: Iterator<Item=Token>>(tokens: &mut I, nested: bool)
+    -> Result<Vec<(String, VdfValue)>, String> {
+    let mut values = Vec::new();
+
+    loop {
+        let key = match tokens.next() {
+            Some(Token::String(key)) => key,
+            Some(Token::Close) if nested => return Ok(values),
+            None if !nested => return Ok(values),
+            Some(token) => return Err(format!("Expected key, found {:?}", token)),
+            None => return Err("Unexpected end of file, expected key or \"}\"".into()),
+        };
+
+        let value = match tokens.next() {
+            Some(Token::String(value)) => VdfValue::String(value),
+            Some(Token::Open) => VdfValue::Table(parse_table(tokens, true)?),
+            Some(Token::Close) => return Err(format!("Expected value for \"{}\", found \"}}\"", key)),
+            None => return Err(format!("Unexpected end of file, expected value for \"{}\"", key)),
+        };
+
+        values.push((key, value));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::VdfValue;
+
+    #[test]
+    fn parse_values() {
+        let data = VdfValue::parse("\"foo\" \"bar\"\n\"bar\" \"foo\"").unwrap();
+        assert_eq!(data.entries().len(), 2);
+        assert_eq!(data.get("foo").unwrap().as_str(), Some("bar"));
+        assert_eq!(data.get("bar").unwrap().as_str(), Some("foo"));
+    }
+
+    #[test]
+    fn parse_nested() {
+        let data = VdfValue::parse(
+            "\"libraryfolders\"\n{\n\t\"0\"\n\t{\n\t\t\"path\"\t\t\"/home/layl/.steam\"\n\t}\n}"
+        ).unwrap();
+        let folder = data.get("LibraryFolders").unwrap().get("0").unwrap();
+        assert_eq!(folder.get("path").unwrap().as_str(), Some("/home/layl/.steam"));
+    }
+
+    #[test]
+    fn parse_comments_and_escapes() {
+        let data = VdfValue::parse("// Steam\n\"path\" \"C:\\\\Games\\\\Steam\" // Windows").unwrap();
+        assert_eq!(data.get("path").unwrap().as_str(), Some("C:\\Games\\Steam"));
+    }
+
+    #[test]
+    fn parse_unclosed() {
+        assert!(VdfValue::parse("\"libraryfolders\" { \"0\" \"/steam\"").is_err());
+    }
+}
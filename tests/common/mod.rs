@@ -0,0 +1,152 @@
+//! Helpers shared by the integration tests, these run the generator on a game tree and read
+//! back the files it generated.
+
+#![allow(dead_code)]
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// A run of the generator in its own temporary folder, which is removed when dropped.
+pub struct TestRun {
+    pub base: PathBuf,
+    pub game_path: PathBuf,
+    pub target_path: PathBuf,
+}
+
+impl TestRun {
+    /// Creates a run using the miniature game tree in tests/fixtures/game.
+    pub fn with_fixture(name: &str) -> Self {
+        let game_path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests").join("fixtures").join("game");
+        TestRun::new(name, game_path)
+    }
+
+    pub fn new(name: &str, game_path: PathBuf) -> Self {
+        let base = env::temp_dir()
+            .join(format!("shattered-test-{}-{}", name, ::std::process::id()));
+        if base.exists() {
+            fs::remove_dir_all(&base).unwrap();
+        }
+        fs::create_dir_all(&base).unwrap();
+
+        TestRun {
+            target_path: base.join("mod").join("shattered"),
+            base: base,
+            game_path: game_path,
+        }
+    }
+
+    /// Writes the config for this run, with extra lines appended to it.
+    pub fn write_config(&self, extra: &str) {
+        write_file(&self.base.join("Config.toml"), &format!(
+            "mod_name = \"Shattered\"\ngame_path = \"{}\"\ntarget_path = \"{}\"\n\
+             tag_map_path = \"{}\"\ncache_path = \"{}\"\nseed = 1\n{}",
+            self.game_path.display(), self.target_path.display(),
+            self.base.join("tag_map.txt").display(), self.base.join("build_cache.txt").display(),
+            extra));
+    }
+
+    /// Creates a run using a copy of the miniature game tree, which the test can change.
+    pub fn with_fixture_copy(name: &str) -> Self {
+        let mut run = TestRun::with_fixture(name);
+        let game_path = run.base.join("game");
+        copy_dir(&run.game_path, &game_path);
+        run.game_path = game_path;
+        run
+    }
+
+    /// Runs the generator with the given arguments, panicking if it fails. Returns what the
+    /// generator printed.
+    pub fn run(&self, args: &[&str]) -> String {
+        let output = self.run_generator(args);
+        let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+        assert!(output.status.success(), "Generator failed:\n{}", stdout);
+        stdout
+    }
+
+    /// Runs the generator with the given arguments, panicking unless it reports an error rather
+    /// than succeeding or crashing. Returns what the generator printed, including errors.
+    pub fn run_failing(&self, args: &[&str]) -> String {
+        let output = self.run_generator(args);
+        let printed = format!("{}{}",
+            String::from_utf8_lossy(&output.stdout), String::from_utf8_lossy(&output.stderr));
+        assert!(output.status.code() == Some(1), "Generator didn't report an error:\n{}", printed);
+        printed
+    }
+
+    fn run_generator(&self, args: &[&str]) -> Output {
+        if !self.base.join("Config.toml").is_file() {
+            self.write_config("");
+        }
+
+        Command::new(env!("CARGO_BIN_EXE_shattered-generator"))
+            .arg("--config").arg(self.base.join("Config.toml"))
+            .args(args)
+            .output().unwrap()
+    }
+
+    /// Reads a file from the generated mod.
+    pub fn read(&self, relative: &str) -> String {
+        read_file(&self.target_path.join(relative))
+    }
+
+    pub fn read_bytes(&self, relative: &str) -> Vec<u8> {
+        let mut data = Vec::new();
+        File::open(self.target_path.join(relative)).unwrap().read_to_end(&mut data).unwrap();
+        data
+    }
+
+    pub fn exists(&self, relative: &str) -> bool {
+        self.target_path.join(relative).is_file()
+    }
+
+    pub fn tag_map(&self) -> String {
+        read_file(&self.base.join("tag_map.txt"))
+    }
+
+    /// Gets the tag the province with the given id got.
+    pub fn tag_for(&self, province_id: &str) -> String {
+        let prefix = format!("{} = ", province_id);
+        self.tag_map().lines()
+            .find(|l| l.starts_with(&prefix))
+            .map(|l| l[prefix.len()..].to_string())
+            .unwrap_or_else(|| panic!("No tag generated for province {}", province_id))
+    }
+}
+
+impl Drop for TestRun {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.base);
+    }
+}
+
+pub fn write_file(path: &Path, text: &str) {
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    File::create(path).unwrap().write_all(text.as_bytes()).unwrap();
+}
+
+pub fn copy_dir(source: &Path, target: &Path) {
+    fs::create_dir_all(target).unwrap();
+    for entry in fs::read_dir(source).unwrap() {
+        let path = entry.unwrap().path();
+        let target = target.join(path.file_name().unwrap());
+        if path.is_dir() {
+            copy_dir(&path, &target);
+        } else {
+            fs::copy(&path, &target).unwrap();
+        }
+    }
+}
+
+pub fn read_file(path: &Path) -> String {
+    let mut data = Vec::new();
+    File::open(path)
+        .unwrap_or_else(|e| panic!("Could not open \"{}\": {}", path.display(), e))
+        .read_to_end(&mut data).unwrap();
+
+    // Game files are Windows-1252, but the fixtures only use the ASCII range of it
+    String::from_utf8(data).unwrap()
+}
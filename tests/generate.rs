@@ -0,0 +1,676 @@
+extern crate zip;
+
+mod common;
+
+use std::fs::{self, File};
+use std::io::Read;
+use common::{TestRun, read_file, write_file};
+
+#[test]
+fn generates_country_per_owned_province() {
+    let run = TestRun::with_fixture("country-per-province");
+    run.run(&["generate"]);
+
+    // Every owned province gets a tag, the unowned one doesn't
+    assert_eq!(run.tag_map().lines().count(), 6);
+    assert!(!run.tag_map().contains("999 ="));
+
+    let tag = run.tag_for("1");
+    let country_tags = run.read("common/country_tags/00_countries.txt");
+    assert!(country_tags.contains(&format!("{} = countries/Stockholm.txt", tag)));
+    assert!(country_tags.contains("SWE = countries/Sweden.txt"));
+    assert!(run.exists("common/countries/Stockholm.txt"));
+}
+
+#[test]
+fn provinces_owned_by_new_countries() {
+    let run = TestRun::with_fixture("province-owners");
+    run.run(&["generate"]);
+
+    let tag = run.tag_for("1");
+    let province = run.read("history/provinces/1 - Stockholm.txt");
+    assert!(province.contains(&format!("owner = {}", tag)));
+    assert!(province.contains(&format!("controller = {}", tag)));
+    assert!(province.contains(&format!("add_core = {}", tag)));
+
+    // Events are cleared, so the controller doesn't change back
+    assert!(!province.contains("1444.11.11"));
+
+    // Unowned provinces are left as they were
+    let unowned = run.read("history/provinces/999 - Atlantis.txt");
+    assert!(!unowned.contains("owner"));
+    assert!(unowned.contains("native_size = 20"));
+}
+
+#[test]
+fn country_history_matches_province() {
+    let run = TestRun::with_fixture("country-history");
+    run.run(&["generate"]);
+
+    let tag = run.tag_for("12");
+    let history = run.read(&format!("history/countries/{} - Skane.txt", tag));
+    assert!(history.contains("primary_culture = danish"));
+    assert!(history.contains("religion = catholic"));
+    assert!(history.contains("government = feudal_monarchy"));
+
+    // Papal states become theocracies, as there can only be one pope
+    let tag = run.tag_for("118");
+    let history = run.read(&format!("history/countries/{} - Roma.txt", tag));
+    assert!(history.contains("government = theocratic_government"));
+    assert!(!history.contains("papal_government"));
+}
+
+#[test]
+fn hre_emperor_and_electors() {
+    let run = TestRun::with_fixture("hre");
+    run.run(&["generate"]);
+
+    // Only Austria's capital stays an elector, and becomes the emperor
+    let capital_tag = run.tag_for("134");
+    let capital = run.read(&format!("history/countries/{} - Wien.txt", capital_tag));
+    assert!(capital.contains("elector = yes"));
+    let other_tag = run.tag_for("133");
+    let other = run.read(&format!("history/countries/{} - Steiermark.txt", other_tag));
+    assert!(other.contains("elector = no"));
+
+    let hre = run.read("history/diplomacy/hre.txt");
+    assert!(hre.contains(&format!("1437.12.9 = {}", capital_tag)));
+}
+
+#[test]
+fn configured_passes() {
+    let run = TestRun::with_fixture("passes");
+    run.write_config(
+        "passes = [\"clear_events\", \"create_countries\", \"copy_culture_religion\"]\n");
+    run.run(&["generate"]);
+
+    // Without the papacy and emperor passes, those are left the way they were
+    let tag = run.tag_for("118");
+    let history = run.read(&format!("history/countries/{} - Roma.txt", tag));
+    assert!(history.contains("government = papal_government"));
+    assert!(!run.exists("history/diplomacy/hre.txt"));
+}
+
+#[test]
+fn scripts() {
+    let run = TestRun::with_fixture("scripts");
+    let script = run.base.join("rules.rhai");
+    common::write_file(&script, r#"
+        fn province(province) {
+            province.get("owner") != "DAN"
+        }
+
+        fn country(country, history, province) {
+            if province.get("culture") == "swedish" {
+                history.set("government", "republic");
+            }
+        }
+    "#);
+    run.write_config(&format!("scripts = [\"{}\"]\n", script.display()));
+    run.run(&["generate"]);
+
+    // Denmark's province is kept by Denmark
+    assert!(!run.tag_map().contains("12 ="));
+    assert!(run.read("history/provinces/12 - Skane.txt").contains("owner = DAN"));
+
+    let tag = run.tag_for("1");
+    let history = run.read(&format!("history/countries/{} - Stockholm.txt", tag));
+    assert!(history.contains("government = republic"));
+}
+
+#[test]
+fn partial_shattering() {
+    let run = TestRun::with_fixture("partial");
+    run.write_config("shatter_regions = [\"scandinavia_region\"]\nexclude_tags = [\"DAN\"]\n");
+    run.run(&["generate"]);
+
+    // Only the Swedish provinces are shattered, everything else keeps its owner and history
+    assert_eq!(run.tag_map().lines().count(), 2);
+    let skane = run.read("history/provinces/12 - Skane.txt");
+    assert!(skane.contains("owner = DAN"));
+    assert!(skane.contains("1658.2.26"));
+    assert!(run.read("history/provinces/134 - Wien.txt").contains("owner = HAB"));
+    assert!(!run.exists("history/diplomacy/hre.txt"));
+}
+
+#[test]
+fn kept_country_capital() {
+    let run = TestRun::with_fixture("kept-capital");
+    run.write_config("shatter_areas = [\"uppland_area\"]\n");
+    run.run(&["generate"]);
+
+    // Sweden keeps Ostergotland, so its capital moves there
+    assert!(run.read("history/provinces/2 - Ostergotland.txt").contains("owner = SWE"));
+    assert!(run.read("history/countries/SWE - Sweden.txt").contains("capital = 2"));
+}
+
+#[test]
+fn clusters() {
+    let run = TestRun::with_fixture("clusters");
+    run.write_config("cluster_size = 2\n");
+    run.run(&["generate"]);
+
+    // Neighbouring provinces of the same owner become one country, named after the first
+    assert_eq!(run.tag_map().lines().count(), 4);
+    let sweden_tag = run.tag_for("1");
+    let ostergotland = run.read("history/provinces/2 - Ostergotland.txt");
+    assert!(ostergotland.contains(&format!("owner = {}", sweden_tag)));
+    let austria_tag = run.tag_for("133");
+    let wien = run.read("history/provinces/134 - Wien.txt");
+    assert!(wien.contains(&format!("owner = {}", austria_tag)));
+
+    // The cluster with Austria's capital keeps its elector status and becomes the emperor
+    let history = run.read(&format!("history/countries/{} - Steiermark.txt", austria_tag));
+    assert!(history.contains("elector = yes"));
+    let hre = run.read("history/diplomacy/hre.txt");
+    assert!(hre.contains(&format!("1437.12.9 = {}", austria_tag)));
+}
+
+#[test]
+fn natives() {
+    let run = TestRun::with_fixture("natives-remove");
+    run.write_config("natives = \"remove\"\n");
+    run.run(&["generate"]);
+
+    let atlantis = run.read("history/provinces/999 - Atlantis.txt");
+    assert!(!atlantis.contains("native_size"));
+    assert!(!atlantis.contains("owner"));
+
+    // Superregions can be handled differently from the rest of the world
+    let run = TestRun::with_fixture("natives-countries");
+    run.write_config("natives = \"remove\"\n\
+                      natives_by_superregion = { new_world_superregion = \"countries\" }\n");
+    run.run(&["generate"]);
+
+    let tag = run.tag_for("999");
+    let atlantis = run.read("history/provinces/999 - Atlantis.txt");
+    assert!(atlantis.contains(&format!("owner = {}", tag)));
+    assert!(atlantis.contains("is_city = yes"));
+    assert!(!atlantis.contains("native_size"));
+    let history = run.read(&format!("history/countries/{} - Atlantis.txt", tag));
+    assert!(history.contains("government = tribal"));
+    assert!(history.contains("primary_culture = atlantean"));
+
+    // Without a country of the same culture, the graphics come from the culture group and the
+    // technology group from the nearest country
+    assert!(run.read("common/countries/Atlantis.txt").contains("graphical_culture = atlanteangfx"));
+    assert!(history.contains("technology_group = western"));
+}
+
+#[test]
+fn cores_and_claims() {
+    let run = TestRun::with_fixture_copy("cores");
+    let province = run.game_path.join("history/provinces/12 - Skane.txt");
+    write_file(&province, &format!("{}add_core = SCA\n", read_file(&province)));
+    run.write_config("shatter_tags = [\"DAN\"]\n");
+    run.run(&["generate"]);
+
+    // Denmark's core goes to the new owner, Sweden's core becomes a claim
+    let skane = run.read("history/provinces/12 - Skane.txt");
+    assert!(skane.contains(&format!("add_core = {}", run.tag_for("12"))));
+    assert!(!skane.contains("add_core = DAN"));
+    assert!(!skane.contains("add_core = SWE"));
+    assert!(skane.contains("add_claim = SWE"));
+
+    // Countries that don't exist at the start keep their cores, so they can be released
+    assert!(skane.contains("add_core = SCA"));
+    assert!(!skane.contains("add_claim = SCA"));
+
+    // The old owner's cores can be kept, even if the old owner doesn't exist anymore
+    let run = TestRun::with_fixture("kept-cores");
+    run.write_config("keep_old_owner_cores = true\n");
+    run.run(&["generate"]);
+
+    let ostergotland = run.read("history/provinces/2 - Ostergotland.txt");
+    assert!(ostergotland.contains("add_core = SWE"));
+    assert!(ostergotland.contains(&format!("add_core = {}", run.tag_for("2"))));
+
+    // Countries split off from Sweden claim each other's provinces
+    let run = TestRun::with_fixture("compatriot-claims");
+    run.write_config("compatriot_claims = true\n");
+    run.run(&["generate"]);
+
+    let ostergotland = run.read("history/provinces/2 - Ostergotland.txt");
+    assert!(ostergotland.contains(&format!("add_permanent_claim = {}", run.tag_for("1"))));
+    assert!(!run.read("history/provinces/12 - Skane.txt").contains("add_claim"));
+}
+
+#[test]
+fn capital_and_technology() {
+    let run = TestRun::with_fixture("technology");
+    run.write_config("technology_groups = { danish = \"nordic\" }\n");
+    run.run(&["generate"]);
+
+    // Countries have their own province as capital, instead of the old country's
+    let tag = run.tag_for("12");
+    let history = run.read(&format!("history/countries/{} - Skane.txt", tag));
+    assert!(history.contains("capital = 12"));
+    assert!(history.contains("fixed_capital = 12"));
+
+    // Unit types come from the other countries of the culture group, unless overridden
+    assert!(history.contains("technology_group = nordic"));
+    assert!(history.contains("unit_type = western"));
+    let tag = run.tag_for("2");
+    let history = run.read(&format!("history/countries/{} - Ostergotland.txt", tag));
+    assert!(history.contains("technology_group = western"));
+}
+
+#[test]
+fn rulers() {
+    let run = TestRun::with_fixture("rulers");
+    run.write_config("generate_heirs = true\ngenerate_queens = true\n");
+    run.run(&["generate"]);
+
+    // Rulers get names from their culture or its group
+    let tag = run.tag_for("2");
+    let history = run.read(&format!("history/countries/{} - Ostergotland.txt", tag));
+    assert!(history.contains("1444.11.11"));
+    assert!(history.contains("monarch = "));
+    assert!(history.contains("heir = "));
+    assert!(history.contains(&format!("country_of_origin = {}", tag)));
+    assert!(history.contains("dynasty = Bonde") || history.contains("dynasty = Vasa"));
+
+    let tag = run.tag_for("12");
+    let history = run.read(&format!("history/countries/{} - Skane.txt", tag));
+    assert!(history.contains("name = Karl") || history.contains("name = Erik") ||
+        history.contains("name = Kristoffer"));
+
+    // Without dynasty names the dynasty is named after the province
+    assert!(history.contains("dynasty = Skane"));
+
+    // Only monarchies get heirs and queens, the papacy became a theocracy
+    let tag = run.tag_for("118");
+    let history = run.read(&format!("history/countries/{} - Roma.txt", tag));
+    assert!(history.contains("government = theocratic_government"));
+    assert!(history.contains("monarch = "));
+    assert!(!history.contains("heir = "));
+    assert!(!history.contains("queen = "));
+
+    // Cultures without names don't get a ruler, and there's none left from the old country
+    let tag = run.tag_for("134");
+    let history = run.read(&format!("history/countries/{} - Wien.txt", tag));
+    assert!(!history.contains("monarch = "));
+    assert!(!history.contains("heir = "));
+    assert!(!history.contains("1444.11.11"));
+}
+
+#[test]
+fn diplomacy() {
+    let run = TestRun::with_fixture("diplomacy");
+    run.run(&["generate"]);
+
+    // Relationships move to the countries that own the capitals now
+    let diplomacy = run.read("history/diplomacy/Scandinavia.txt");
+    assert!(diplomacy.contains(&format!("first = {}", run.tag_for("1"))));
+    assert!(diplomacy.contains(&format!("second = {}", run.tag_for("12"))));
+    assert!(diplomacy.contains(&format!("second = {}", run.tag_for("134"))));
+
+    // Countries that aren't shattered keep their relationships
+    let run = TestRun::with_fixture("diplomacy-partial");
+    run.write_config("exclude_tags = [\"DAN\"]\n");
+    run.run(&["generate"]);
+
+    let diplomacy = run.read("history/diplomacy/Scandinavia.txt");
+    assert!(diplomacy.contains("second = DAN"));
+    assert!(!diplomacy.contains("first = SWE"));
+}
+
+#[test]
+fn rewrite_tags() {
+    let run = TestRun::with_fixture_copy("rewrite-tags");
+    let province = run.game_path.join("history/provinces/2 - Ostergotland.txt");
+    write_file(&province, &format!("{}add_claim = SWE\n", read_file(&province)));
+    run.write_config("shatter_tags = [\"SWE\"]\n");
+    run.run(&["generate"]);
+
+    // Sweden is gone, so references to it go to whoever has Stockholm now
+    let sweden_tag = run.tag_for("1");
+    let war = run.read("history/wars/ScandinavianWar.txt");
+    assert!(war.contains(&format!("add_attacker = {}", sweden_tag)));
+    assert!(war.contains("add_defender = DAN"));
+    let denmark = run.read("history/countries/DAN - Denmark.txt");
+    assert!(denmark.contains(&format!("add_historical_rival = {}", sweden_tag)));
+
+    // In a province they go to its owner
+    let skane = run.read("history/provinces/12 - Skane.txt");
+    assert!(!skane.contains("SWE"));
+    let ostergotland = run.read("history/provinces/2 - Ostergotland.txt");
+    assert!(!ostergotland.contains("SWE"));
+
+    // Except for claims, the owner doesn't claim its own province
+    assert!(ostergotland.contains(&format!("owner = {}", run.tag_for("2"))));
+    assert!(!ostergotland.contains("add_claim"));
+}
+
+#[test]
+fn localisation_and_flags() {
+    let run = TestRun::with_fixture("polish");
+    run.run(&["generate"]);
+
+    // The original localisation is kept, with the new countries added to it
+    let localisation = run.read("localisation/countries_l_english.yml");
+    assert!(localisation.contains(" SWE: \"Sweden\""));
+
+    for line in run.tag_map().lines() {
+        let tag = line.split(" = ").nth(1).unwrap();
+        assert!(localisation.contains(&format!(" {}: ", tag)));
+        assert!(localisation.contains(&format!(" {}_ADJ: ", tag)));
+        assert!(run.exists(&format!("gfx/flags/{}.tga", tag)));
+    }
+}
+
+#[test]
+fn descriptors() {
+    let run = TestRun::with_fixture("descriptors");
+    run.run(&["generate"]);
+
+    let descriptor = run.read("descriptor.mod");
+    assert!(descriptor.contains("name = \"Shattered\""));
+    assert!(descriptor.contains("supported_version = \"1.30.*\""));
+    assert!(!descriptor.contains("path ="));
+
+    let outer = ::common::read_file(&run.target_path.with_file_name("shattered.mod"));
+    assert!(outer.contains("path = \"mod/shattered\""));
+}
+
+#[test]
+fn zip_output() {
+    let run = TestRun::with_fixture("zip-output");
+    run.write_config("output_format = \"zip\"\n");
+    run.run(&["generate"]);
+    assert!(!run.target_path.exists());
+
+    // The launcher expects the mod's files at the root of the archive
+    let archive_path = run.target_path.with_file_name("shattered.zip");
+    let mut archive = zip::ZipArchive::new(File::open(&archive_path).unwrap()).unwrap();
+    let names: Vec<String> = (0..archive.len())
+        .map(|i| archive.by_index(i).unwrap().name().to_string())
+        .collect();
+    assert!(names.iter().any(|n| n == ".shattered-generator"));
+    assert!(names.iter().any(|n| n == "descriptor.mod"));
+    for folder in &["history/", "common/", "localisation/", "gfx/flags/"] {
+        assert!(names.iter().any(|n| n.starts_with(folder)), "Missing {}", folder);
+    }
+
+    let outer = read_file(&run.target_path.with_file_name("shattered.mod"));
+    assert!(outer.contains("archive = \"mod/shattered.zip\""));
+    assert!(!outer.contains("path = "));
+
+    // The archive is re-used when only the descriptor changes
+    run.write_config("output_format = \"zip\"\nmod_tags = [\"Gameplay\"]\n");
+    let output = run.run(&["generate"]);
+    assert!(output.contains("Only the output settings changed since the last run"));
+    let mut archive = zip::ZipArchive::new(File::open(&archive_path).unwrap()).unwrap();
+    assert!(archive.by_name("history/provinces/1 - Stockholm.txt").is_ok());
+    let mut descriptor = String::new();
+    archive.by_name("descriptor.mod").unwrap().read_to_string(&mut descriptor).unwrap();
+    assert!(descriptor.contains("\"Gameplay\""));
+}
+
+#[test]
+fn same_seed_same_output() {
+    let first = TestRun::with_fixture("seed-first");
+    first.run(&["generate"]);
+    let second = TestRun::with_fixture("seed-second");
+    second.run(&["generate"]);
+
+    let tag = first.tag_for("1");
+    assert_eq!(
+        first.read("common/countries/Stockholm.txt"), second.read("common/countries/Stockholm.txt"));
+    let flag = format!("gfx/flags/{}.tga", tag);
+    assert!(first.read_bytes(&flag) == second.read_bytes(&flag));
+}
+
+#[test]
+fn refuses_to_overwrite_unowned_target() {
+    let run = TestRun::with_fixture("unowned-target");
+    let user_file = run.target_path.join("notes.txt");
+    write_file(&user_file, "not a mod");
+
+    let output = run.run_failing(&["generate"]);
+    assert!(output.contains("refusing to overwrite it"));
+    assert_eq!(read_file(&user_file), "not a mod");
+
+    // With --force it's replaced anyway
+    run.run(&["generate", "--force"]);
+    assert!(!user_file.exists());
+    assert!(run.exists(".shattered-generator"));
+}
+
+#[test]
+fn refuses_to_overwrite_unowned_descriptor() {
+    let run = TestRun::with_fixture("unowned-descriptor");
+    let outer_path = run.target_path.with_file_name("shattered.mod");
+    write_file(&outer_path, "name = \"Someone else's mod\"\npath = \"mod/other\"\n");
+
+    run.run_failing(&["generate"]);
+    assert!(!run.target_path.exists());
+    assert_eq!(read_file(&outer_path), "name = \"Someone else's mod\"\npath = \"mod/other\"\n");
+
+    // With --force it's replaced anyway
+    run.run(&["generate", "--force"]);
+    assert!(read_file(&outer_path).contains("path = \"mod/shattered\""));
+}
+
+#[test]
+fn stale_build_is_removed() {
+    let run = TestRun::with_fixture("stale-build");
+    let build_path = run.base.join("mod").join(".shattered.build");
+    write_file(&build_path.join(".shattered-generator"), "");
+    write_file(&build_path.join("leftover.txt"), "from a failed run");
+
+    run.run(&["generate"]);
+    assert!(!build_path.exists());
+    assert!(!run.exists("leftover.txt"));
+}
+
+#[test]
+fn failed_run_keeps_previous_mod() {
+    let run = TestRun::with_fixture("failed-run");
+    run.run(&["generate"]);
+    let descriptor = run.read("descriptor.mod");
+    let outer_path = run.target_path.with_file_name("shattered.mod");
+    let outer = read_file(&outer_path);
+
+    // A build folder that isn't ours makes the run fail after the mod was already processed
+    let build_path = run.base.join("mod").join(".shattered.build");
+    write_file(&build_path.join("notes.txt"), "not a mod");
+    run.write_config("mod_tags = [\"Gameplay\"]\n");
+    run.run_failing(&["generate", "--no-cache"]);
+
+    assert_eq!(run.read("descriptor.mod"), descriptor);
+    assert_eq!(read_file(&outer_path), outer);
+    assert!(run.exists(".shattered-generator"));
+    assert_eq!(read_file(&build_path.join("notes.txt")), "not a mod");
+}
+
+#[test]
+fn unchanged_inputs_skip_generation() {
+    let run = TestRun::with_fixture_copy("cache-skip");
+    run.run(&["generate"]);
+    let output = run.run(&["generate"]);
+    assert!(output.contains("Nothing changed since the last run"));
+
+    // Changing a game file regenerates the mod
+    let province = run.game_path.join("history/provinces/2 - Ostergotland.txt");
+    write_file(&province, &format!("{}discovered_by = western\n", read_file(&province)));
+    let output = run.run(&["generate"]);
+    assert!(!output.contains("Nothing changed since the last run"));
+    assert!(run.read("history/provinces/2 - Ostergotland.txt").contains("discovered_by = western"));
+}
+
+#[cfg(unix)]
+#[test]
+fn changed_settings_only_rerun_affected_stages() {
+    let run = TestRun::with_fixture("cache-stages");
+    run.run(&["generate"]);
+
+    // Settings for how the mod is written don't need the game data to be processed again
+    run.write_config("mod_tags = [\"Gameplay\"]\n");
+    let output = run.run(&["generate"]);
+    assert!(output.contains("Only the output settings changed since the last run"));
+    assert!(!output.contains("=== processing ==="));
+    assert!(run.read("descriptor.mod").contains("\"Gameplay\""));
+    assert!(run.exists("history/provinces/1 - Stockholm.txt"));
+    assert!(run.exists(&format!("gfx/flags/{}.tga", run.tag_for("1"))));
+
+    // Other settings do, but the localisation doesn't change with them
+    run.write_config("mod_tags = [\"Gameplay\"]\ngenerate_heirs = true\n");
+    let output = run.run(&["generate"]);
+    assert!(output.contains("=== processing ==="));
+    assert!(output.contains("The game files didn't change since the last run"));
+    assert!(output.contains("Re-used the unchanged country localisation"));
+    assert!(run.read("localisation/countries_l_english.yml").contains(" SWE: \"Sweden\""));
+}
+
+#[test]
+fn unchanged_flags_are_reused() {
+    use std::os::unix::fs::MetadataExt;
+
+    let run = TestRun::with_fixture_copy("cache-flags");
+    run.run(&["generate"]);
+    let flag = run.target_path.join(format!("gfx/flags/{}.tga", run.tag_for("1")));
+    let inode = fs::metadata(&flag).unwrap().ino();
+
+    // A change that doesn't affect any flags links the previous flags instead of rendering them
+    let province = run.game_path.join("history/provinces/2 - Ostergotland.txt");
+    write_file(&province, &format!("{}discovered_by = western\n", read_file(&province)));
+    let output = run.run(&["generate"]);
+    assert!(output.contains("Re-used 6 unchanged flags"));
+    assert_eq!(fs::metadata(&flag).unwrap().ino(), inode);
+}
+
+#[test]
+fn dry_run_writes_nothing() {
+    let run = TestRun::with_fixture("dry-run");
+    run.run(&["generate", "--dry-run"]);
+
+    assert!(!run.target_path.exists());
+    assert!(!run.base.join("tag_map.txt").exists());
+}
+
+#[test]
+fn invalid_seed_on_command_line() {
+    let run = TestRun::with_fixture("invalid-seed");
+    for seed in &["--seed=abc", "--seed=-1", "--seed=9223372036854775808"] {
+        let output = run.run_failing(&["generate", seed]);
+        assert!(output.contains("should be a number from 0 to 9223372036854775807"));
+    }
+    assert!(!run.target_path.exists());
+
+    run.run(&["generate", "--seed=9223372036854775807"]);
+}
+
+#[test]
+fn paths_from_command_line() {
+    let run = TestRun::with_fixture("cli-paths");
+    write_file(&run.base.join("Config.toml"), &format!(
+        "mod_name = \"Shattered\"\ntag_map_path = \"{}\"\ncache_path = \"{}\"\nseed = 1\n",
+        run.base.join("tag_map.txt").display(), run.base.join("build_cache.txt").display()));
+
+    // Paths missing from the config can be given on the command line instead
+    let game_path = run.game_path.display().to_string();
+    let target_path = run.target_path.display().to_string();
+    run.run(&["generate", "--game-path", &game_path, "--target-path", &target_path]);
+
+    assert!(run.exists("descriptor.mod"));
+}
+
+#[test]
+fn validate_command() {
+    let run = TestRun::with_fixture("validate");
+    let output = run.run(&["validate"]);
+
+    assert!(output.contains("=== validation successful ==="));
+    assert!(output.contains("6 countries can be generated"));
+    assert!(!run.target_path.exists());
+}
+
+#[test]
+fn stats_command() {
+    let run = TestRun::with_fixture("stats");
+    let output = run.run(&["stats"]);
+
+    assert!(output.contains("Provinces: 7\n"));
+    assert!(output.contains("Original countries: 4\n"));
+    assert!(output.contains("Generated countries: 6\n"));
+    assert!(output.contains("Flags: 6\n"));
+    assert!(!run.target_path.exists());
+}
+
+#[test]
+fn diff_command() {
+    let run = TestRun::with_fixture_copy("diff");
+    let output = run.run(&["diff"]);
+    assert!(output.contains("+ descriptor.mod\n"));
+    assert!(!run.target_path.exists());
+
+    run.run(&["generate"]);
+    let output = run.run(&["diff"]);
+    assert!(output.contains("0 files changed\n"));
+
+    let province = run.game_path.join("history/provinces/2 - Ostergotland.txt");
+    write_file(&province, &format!("{}discovered_by = western\n", read_file(&province)));
+    let output = run.run(&["diff"]);
+    assert!(output.contains("~ history/provinces/2 - Ostergotland.txt\n"));
+    assert!(output.contains("1 files changed\n"));
+
+    // The mod it compared against is removed again
+    let diff_path = output.lines()
+        .find(|line| line.starts_with("Moving finished mod to "))
+        .map(|line| line.split('"').nth(1).unwrap().to_string())
+        .unwrap();
+    assert!(!::std::path::Path::new(&diff_path).parent().unwrap().exists());
+}
+
+#[test]
+fn flags_command() {
+    let run = TestRun::with_fixture("flags");
+    run.run(&["generate"]);
+    let flag = format!("gfx/flags/{}.tga", run.tag_for("1"));
+    fs::remove_file(run.target_path.join(&flag)).unwrap();
+
+    let output = run.run(&["flags"]);
+    assert!(output.contains("=== generating flags ==="));
+    assert!(run.exists(&flag));
+
+    // Flags can't be written into an archive
+    run.write_config("output_format = \"zip\"\n");
+    run.run_failing(&["flags"]);
+}
+
+#[test]
+fn tags_kept_between_runs() {
+    let run = TestRun::with_fixture("stable-tags");
+    run.run(&["generate"]);
+    let tag_map = run.tag_map();
+
+    // A different seed changes everything else, but the provinces keep their tags
+    run.run(&["generate", "--seed", "2"]);
+    assert_eq!(run.tag_map(), tag_map);
+    for &(province_id, file) in &[("1", "1 - Stockholm.txt"), ("2", "2 - Ostergotland.txt")] {
+        let history = run.read(&format!("history/provinces/{}", file));
+        assert!(history.contains(&format!("owner = {}", run.tag_for(province_id))));
+    }
+}
+
+#[test]
+fn tags_taken_by_game_are_reassigned() {
+    let run = TestRun::with_fixture_copy("taken-tags");
+    run.run(&["generate"]);
+    let taken = run.tag_for("1");
+    let kept = run.tag_for("2");
+
+    // The game now uses the tag we gave Stockholm for a country of its own
+    let country_tags = run.game_path.join("common/country_tags/00_countries.txt");
+    write_file(&country_tags, &format!(
+        "{}{} = \"countries/Sweden.txt\"\n", read_file(&country_tags), taken));
+    let output = run.run(&["generate"]);
+
+    let new = run.tag_for("1");
+    assert!(new != taken);
+    assert!(output.contains(&format!(
+        "Tag {} for Stockholm is now used by the game, reassigned to {}", taken, new)));
+    assert!(run.read("history/provinces/1 - Stockholm.txt").contains(&format!("owner = {}", new)));
+    assert_eq!(run.tag_for("2"), kept);
+}
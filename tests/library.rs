@@ -0,0 +1,90 @@
+extern crate shattered_generator;
+
+mod common;
+
+use shattered_generator::{passes, load_eu4_data, process_eu4_data, write_mod, Eu4TargetData};
+use shattered_generator::cache::BuildCache;
+use shattered_generator::config::Config;
+use shattered_generator::passes::{Pass, PassContext};
+use shattered_generator::seed::Seed;
+use shattered_generator::tags::TagMap;
+use common::{TestRun, write_file};
+
+#[test]
+fn pipeline_through_library() {
+    let run = TestRun::with_fixture("library");
+    run.write_config("");
+    let config = Config::load(run.base.join("Config.toml")).unwrap();
+
+    let source_data = load_eu4_data(&config);
+    assert_eq!(source_data.provinces.len(), 7);
+    assert_eq!(source_data.countries.len(), 4);
+
+    let mut tag_map = TagMap::load(&config.tag_map_path);
+    let passes = passes::get_passes(&config).unwrap();
+    let mut target_data = process_eu4_data(source_data, &passes, &mut tag_map, Seed(1));
+    assert_eq!(target_data.countries.len(), 6);
+    assert_eq!(target_data.flag_requests.len(), 6);
+
+    // Custom variants can change the data between stages
+    for localization in &mut target_data.localizations {
+        localization.string = format!("Free {}", localization.string);
+    }
+    write_mod(&config, &target_data, false, &mut BuildCache::disabled()).unwrap();
+
+    let localisation = run.read("localisation/countries_l_english.yml");
+    assert!(localisation.contains("\"Free Stockholm\""));
+}
+
+struct RenameCountries;
+
+impl Pass for RenameCountries {
+    fn name(&self) -> &'static str { "rename_countries" }
+
+    fn run(&self, data: &mut Eu4TargetData, _context: &mut PassContext) {
+        for localization in &mut data.localizations {
+            localization.string = format!("Greater {}", localization.string);
+        }
+    }
+}
+
+#[test]
+fn custom_pass() {
+    let run = TestRun::with_fixture("library-pass");
+    run.write_config("");
+    let config = Config::load(run.base.join("Config.toml")).unwrap();
+
+    let mut passes = passes::get_passes(&config).unwrap();
+    passes.push(Box::new(RenameCountries));
+    let mut tag_map = TagMap::load(&config.tag_map_path);
+    let target_data = process_eu4_data(load_eu4_data(&config), &passes, &mut tag_map, Seed(1));
+
+    assert!(target_data.localizations.iter().all(|l| l.string.starts_with("Greater ")));
+    assert_eq!(target_data.origins.len(), target_data.countries.len());
+}
+
+#[test]
+fn random_seed_fits_in_config() {
+    let run = TestRun::with_fixture("library-seed");
+    let config_path = run.base.join("Config.toml");
+
+    // A printed random seed has to be usable in the config to reproduce the run
+    for _ in 0..100 {
+        let seed = Seed::random();
+        write_file(&config_path, &format!(
+            "mod_name = \"Shattered\"\ngame_path = \"{}\"\ntarget_path = \"{}\"\nseed = {}\n",
+            run.game_path.display(), run.target_path.display(), seed.0));
+        let config = Config::load(&config_path).unwrap();
+        assert_eq!(config.seed, Some(seed.0));
+    }
+}
+
+#[test]
+fn negative_seed_is_a_problem() {
+    let run = TestRun::with_fixture("library-negative-seed");
+    let config_path = run.base.join("Config.toml");
+    write_file(&config_path, "mod_name = \"Shattered\"\nseed = -1\n");
+
+    let error = Config::load(&config_path).err().unwrap().to_string();
+    assert!(error.contains("Key \"seed\" should be a number of at least 0"));
+}
@@ -0,0 +1,44 @@
+mod common;
+
+use common::{TestRun, write_file};
+
+/// The files of a small game tree, provinces are owned by two countries so they get new tags.
+const GAME_FILES: &'static [(&'static str, &'static str)] = &[
+    ("history/provinces/1 - Stockholm.txt",
+     "owner = SWE\ncontroller = SWE\nadd_core = SWE\nculture = swedish\nreligion = catholic\n"),
+    ("history/provinces/2 - Ostergotland.txt",
+     "owner = SWE\ncontroller = SWE\nadd_core = SWE\nculture = swedish\nreligion = catholic\n"),
+    ("history/provinces/3 - Kalmar.txt",
+     "owner = DAN\ncontroller = DAN\nadd_core = DAN\nculture = swedish\nreligion = catholic\n"),
+    ("history/provinces/12 - Skane.txt",
+     "owner = DAN\ncontroller = DAN\nadd_core = DAN\nculture = danish\nreligion = catholic\n"),
+    ("history/provinces/Thumbs.db", "not a data file"),
+    ("history/provinces/old/4 - Gotland.txt", "owner = DAN\n"),
+    ("history/countries/SWE - Sweden.txt",
+     "government = feudal_monarchy\nprimary_culture = swedish\nreligion = catholic\ncapital = 1\n"),
+    ("history/countries/DAN - Denmark.txt",
+     "government = feudal_monarchy\nprimary_culture = danish\nreligion = catholic\ncapital = 12\n"),
+    ("common/countries/Sweden.txt", "graphical_culture = scandinaviangfx\ncolor = { 6 86 182 }\n"),
+    ("common/countries/Denmark.txt", "graphical_culture = scandinaviangfx\ncolor = { 153 24 24 }\n"),
+    ("common/country_tags/00_countries.txt",
+     "SWE = \"countries/Sweden.txt\"\nDAN = \"countries/Denmark.txt\"\n"),
+    ("localisation/countries_l_english.yml", "l_english:\n SWE: \"Sweden\"\n DAN: \"Denmark\"\n"),
+];
+
+#[test]
+fn tags_only_from_data_files() {
+    let game_path = ::std::env::temp_dir()
+        .join(format!("shattered-test-tag-order-game-{}", ::std::process::id()));
+    for &(path, text) in GAME_FILES {
+        write_file(&game_path.join(path), text);
+    }
+
+    let run = TestRun::new("tag-order", game_path.clone());
+    run.run(&["generate"]);
+    let tag_map = run.tag_map();
+    ::std::fs::remove_dir_all(&game_path).unwrap();
+
+    // Provinces get their tags in the order of their file names, stray files don't get any
+    let provinces: Vec<_> = tag_map.lines().map(|l| l.split(" = ").next().unwrap()).collect();
+    assert_eq!(provinces, vec!["1", "12", "2", "3"]);
+}